@@ -0,0 +1,81 @@
+#!/usr/bin/env python3
+"""Regenerates src/unicode.rs from the Unicode database bundled with Python.
+
+Python identifiers are defined with the same XID_Start and XID_Continue
+properties rox uses, so str.isidentifier() gives both sets directly.
+
+    python3 scripts/generate_unicode_tables.py > src/unicode.rs
+"""
+
+import sys
+import unicodedata
+
+
+def ranges(predicate):
+    result = []
+    start = None
+    for code in range(sys.maxunicode + 1):
+        if predicate(code):
+            if start is None:
+                start = code
+        elif start is not None:
+            result.append((start, code - 1))
+            start = None
+    if start is not None:
+        result.append((start, sys.maxunicode))
+    return result
+
+
+def is_surrogate(code):
+    return 0xD800 <= code <= 0xDFFF
+
+
+def xid_start(code):
+    return not is_surrogate(code) and code != ord("_") and chr(code).isidentifier()
+
+
+def xid_continue(code):
+    return not is_surrogate(code) and ("a" + chr(code)).isidentifier()
+
+
+def emit_table(name, table):
+    print(f"const {name}: &[(char, char)] = &[")
+    for start, end in table:
+        print(f"    ('\\u{{{start:x}}}', '\\u{{{end:x}}}'),")
+    print("];")
+
+
+print(f"// Generated by scripts/generate_unicode_tables.py from Unicode "
+      f"{unicodedata.unidata_version}. Do not edit by hand.")
+print()
+print("""fn in_table(c: char, table: &[(char, char)]) -> bool {
+    return table
+        .binary_search_by(|&(start, end)| {
+            if c < start {
+                std::cmp::Ordering::Greater
+            } else if c > end {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok();
+}
+
+pub fn is_xid_start(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphabetic();
+    }
+    return in_table(c, XID_START);
+}
+
+pub fn is_xid_continue(c: char) -> bool {
+    if c.is_ascii() {
+        return c.is_ascii_alphanumeric() || c == '_';
+    }
+    return in_table(c, XID_CONTINUE);
+}
+""")
+emit_table("XID_START", ranges(xid_start))
+print()
+emit_table("XID_CONTINUE", ranges(xid_continue))
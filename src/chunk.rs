@@ -1,6 +1,6 @@
 use crate::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum OpCode {
     OpConstant = 1,
@@ -27,6 +27,74 @@ pub enum OpCode {
     OpJumpIfFalse = 22,
     OpLoop = 23,
     OpReturn = 24,
+    OpStringify = 25,
+    OpCall = 26,
+    OpGetUpvalue = 27,
+    OpSetUpvalue = 28,
+    OpClosure = 29,
+    OpCloseUpvalue = 30,
+    OpGetProperty = 31,
+    OpSetProperty = 32,
+    OpClass = 33,
+    OpMethod = 34,
+    OpInvoke = 35,
+    OpInherit = 36,
+    OpGetSuper = 37,
+    OpSuperInvoke = 38,
+    OpConstantLong = 39,
+    OpGetGlobalLong = 40,
+    OpDefineGlobalLong = 41,
+    OpSetGlobalLong = 42,
+    OpClosureLong = 43,
+    OpGetPropertyLong = 44,
+    OpSetPropertyLong = 45,
+    OpClassLong = 46,
+    OpMethodLong = 47,
+    OpInvokeLong = 48,
+    OpGetSuperLong = 49,
+    OpSuperInvokeLong = 50,
+    OpNotEqual = 51,
+}
+
+impl OpCode {
+    /// Whether the instruction carries a 24-bit constant index instead of a single byte.
+    pub fn is_long(&self) -> bool {
+        return matches!(
+            self,
+            OpCode::OpConstantLong
+                | OpCode::OpGetGlobalLong
+                | OpCode::OpDefineGlobalLong
+                | OpCode::OpSetGlobalLong
+                | OpCode::OpClosureLong
+                | OpCode::OpGetPropertyLong
+                | OpCode::OpSetPropertyLong
+                | OpCode::OpClassLong
+                | OpCode::OpMethodLong
+                | OpCode::OpInvokeLong
+                | OpCode::OpGetSuperLong
+                | OpCode::OpSuperInvokeLong
+        );
+    }
+
+    /// The long counterpart of an instruction that takes a constant index.
+    /// Panics for any other instruction.
+    pub fn long_form(&self) -> OpCode {
+        match self {
+            OpCode::OpConstant => return OpCode::OpConstantLong,
+            OpCode::OpGetGlobal => return OpCode::OpGetGlobalLong,
+            OpCode::OpDefineGlobal => return OpCode::OpDefineGlobalLong,
+            OpCode::OpSetGlobal => return OpCode::OpSetGlobalLong,
+            OpCode::OpClosure => return OpCode::OpClosureLong,
+            OpCode::OpGetProperty => return OpCode::OpGetPropertyLong,
+            OpCode::OpSetProperty => return OpCode::OpSetPropertyLong,
+            OpCode::OpClass => return OpCode::OpClassLong,
+            OpCode::OpMethod => return OpCode::OpMethodLong,
+            OpCode::OpInvoke => return OpCode::OpInvokeLong,
+            OpCode::OpGetSuper => return OpCode::OpGetSuperLong,
+            OpCode::OpSuperInvoke => return OpCode::OpSuperInvokeLong,
+            _ => panic!("{:?} has no long form", self),
+        }
+    }
 }
 
 impl std::convert::From<u8> for OpCode {
@@ -55,61 +123,165 @@ impl std::convert::From<u8> for OpCode {
             21 => OpCode::OpJump, 
             22 => OpCode::OpJumpIfFalse, 
             23 => OpCode::OpLoop, 
+            25 => OpCode::OpStringify,
+            26 => OpCode::OpCall,
+            27 => OpCode::OpGetUpvalue,
+            28 => OpCode::OpSetUpvalue,
+            29 => OpCode::OpClosure,
+            30 => OpCode::OpCloseUpvalue,
+            31 => OpCode::OpGetProperty,
+            32 => OpCode::OpSetProperty,
+            33 => OpCode::OpClass,
+            34 => OpCode::OpMethod,
+            35 => OpCode::OpInvoke,
+            36 => OpCode::OpInherit,
+            37 => OpCode::OpGetSuper,
+            38 => OpCode::OpSuperInvoke,
+            39 => OpCode::OpConstantLong,
+            40 => OpCode::OpGetGlobalLong,
+            41 => OpCode::OpDefineGlobalLong,
+            42 => OpCode::OpSetGlobalLong,
+            43 => OpCode::OpClosureLong,
+            44 => OpCode::OpGetPropertyLong,
+            45 => OpCode::OpSetPropertyLong,
+            46 => OpCode::OpClassLong,
+            47 => OpCode::OpMethodLong,
+            48 => OpCode::OpInvokeLong,
+            49 => OpCode::OpGetSuperLong,
+            50 => OpCode::OpSuperInvokeLong,
+            51 => OpCode::OpNotEqual,
             _ => OpCode::OpReturn,
         }
     } 
 }
 
-#[derive(Copy, Clone)]
+/// Largest index a long constant instruction can address.
+pub const CONSTANT_LONG_MAX: usize = (1 << 24) - 1;
+
+/// Identity of a constant for deduplication. Numbers compare by bit pattern so
+/// that `0` and `-0` stay distinct, and strings by their interned pointer.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(usize),
+}
+
+impl ConstantKey {
+    fn create(value: &value::Value) -> Option<ConstantKey> {
+        match value.get_value_type() {
+            value::ValueType::ValNumber => {
+                return Some(ConstantKey::Number(value.get_value().get_number().to_bits()));
+            }
+            value::ValueType::ValObj => match value.get_value().get_obj() {
+                object::Obj::String(string) => {
+                    return Some(ConstantKey::String(std::rc::Rc::as_ptr(&string) as usize));
+                }
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Chunk {
-    count: i64,
-    capacity: i64,
-    code: [u8; 256],
-    lines: [i64; 256],
+    code: Vec<u8>,
+    lines: Vec<i64>,
+    columns: Vec<i64>,
     constants: value::ValueArray,
+    constant_indexes: std::collections::HashMap<ConstantKey, usize>,
+}
+
+impl Default for Chunk {
+    fn default() -> Chunk {
+        return Chunk::new();
+    }
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
         return Chunk {
-            count: 0,
-            capacity: 0,
-            code: [0; 256],
-            lines: [0; 256],
+            code: Vec::new(),
+            lines: Vec::new(),
+            columns: Vec::new(),
             constants: value::ValueArray::new(),
+            constant_indexes: std::collections::HashMap::new(),
         };
     }
 
-    pub fn get_code(&self) -> [u8; 256] {
-        return self.code;
+    pub fn get_code(&self) -> &[u8] {
+        return &self.code;
+    }
+
+    pub fn get_constants(&self) -> &value::ValueArray {
+        return &self.constants;
     }
 
-    pub fn get_constants(&self) -> value::ValueArray {
-        return self.constants;
+    pub fn get_lines(&self) -> &[i64] {
+        return &self.lines;
     }
 
-    pub fn get_lines(&self) -> [i64; 256] {
-        return self.lines;
+    pub fn get_columns(&self) -> &[i64] {
+        return &self.columns;
     }
 
     pub fn get_count(&self) -> i64 {
-        return self.count;
+        return self.code.len() as i64;
     }
 
-    pub fn write_chunk(&mut self, byte: u8, line: i64) {
-        self.code[self.count as usize] = byte;
-        self.lines[self.count as usize] = line;
-        self.count += 1;
+    pub fn write_chunk(&mut self, byte: u8, line: i64, column: i64) {
+        self.code.push(byte);
+        self.lines.push(line);
+        self.columns.push(column);
     }
 
-    pub fn add_constant(&mut self, value: value::Value) -> i64 {
-        value.write_value_array(self.constants);
-        self.count += 1; 
+    /// Overwrites an already written byte, used to back-patch jump operands.
+    pub fn set_code(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
+    }
+
+    /// Replaces the whole instruction stream, keeping the constant pool.
+    pub fn replace_code(&mut self, code: Vec<u8>, lines: Vec<i64>, columns: Vec<i64>) {
+        self.code = code;
+        self.lines = lines;
+        self.columns = columns;
+    }
 
-        return self.constants.get_count() as i64;
+    /// Decodes the constant index following the instruction at `offset`,
+    /// returning it with the number of bytes it occupies.
+    pub fn get_constant_operand(&self, offset: usize) -> (usize, usize) {
+        let instruction: OpCode = self.code[offset].into();
+        if !instruction.is_long() {
+            return (self.code[offset + 1] as usize, 1);
+        }
+        let constant: usize = ((self.code[offset + 1] as usize) << 16)
+            | ((self.code[offset + 2] as usize) << 8)
+            | self.code[offset + 3] as usize;
+        return (constant, 3);
+    }
+
+    /// Adds a constant to the pool, reusing the slot of an identical number or
+    /// string that is already there.
+    pub fn add_constant(&mut self, value: value::Value) -> usize {
+        let key: Option<ConstantKey> = ConstantKey::create(&value);
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indexes.get(key)) {
+            return *index;
+        }
+
+        value.write_value_array(&mut self.constants);
+        let index: usize = self.constants.get_count() - 1;
+        if let Some(key) = key {
+            self.constant_indexes.insert(key, index);
+        }
+        return index;
     }
 
     pub fn print(&self) {
-        println!("CHUNK: \n\tCOUNT: {}, \n\tCAP: {}, \n\tCODE: {:?}, \n\tLINES: {:?}", self.count, self.capacity, self.code, self.lines);
+        println!(
+            "CHUNK: \n\tCOUNT: {}, \n\tCODE: {:?}, \n\tLINES: {:?}",
+            self.code.len(),
+            self.code,
+            self.lines
+        );
     }
 }
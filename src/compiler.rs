@@ -1,27 +1,11 @@
 use crate::*;
 
+pub type ParseFn = fn(&mut Parser, &mut vm::VM, &mut Compiler, &mut scanner::Scanner, bool);
+
 #[derive(Copy, Clone)]
 pub struct ParseRule {
-    pub prefix: fn(
-        &mut Parser,
-        &mut vm::VM,
-        &mut Compiler,
-        &mut table::Table,
-        &mut scanner::Scanner,
-        bool,
-        &std::collections::HashMap<scanner::TokenType, ParseRule>,
-    ) -> (),
-
-    pub infix: fn(
-        &mut Parser,
-        &mut vm::VM,
-        &mut Compiler,
-        &mut table::Table,
-        &mut scanner::Scanner,
-        bool,
-        &std::collections::HashMap<scanner::TokenType, ParseRule>,
-    ) -> (),
-
+    pub prefix: Option<ParseFn>,
+    pub infix: Option<ParseFn>,
     pub precedence: Precedence,
 }
 
@@ -40,226 +24,818 @@ pub enum Precedence {
     PrecPrimary,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Precedence {
+    /// The next-higher level, used so binary operators associate to the left.
+    pub fn next(self) -> Precedence {
+        match self {
+            Precedence::PrecNone => return Precedence::PrecAssignment,
+            Precedence::PrecAssignment => return Precedence::PrecOr,
+            Precedence::PrecOr => return Precedence::PrecAnd,
+            Precedence::PrecAnd => return Precedence::PrecEquality,
+            Precedence::PrecEquality => return Precedence::PrecComparison,
+            Precedence::PrecComparison => return Precedence::PrecTerm,
+            Precedence::PrecTerm => return Precedence::PrecFactor,
+            Precedence::PrecFactor => return Precedence::PrecUnary,
+            Precedence::PrecUnary => return Precedence::PrecCall,
+            Precedence::PrecCall | Precedence::PrecPrimary => return Precedence::PrecPrimary,
+        }
+    }
+}
+
+/// The Pratt parse rule for each token type. The match is exhaustive, so
+/// every token type has exactly one rule.
+pub fn get_rule(token_type: scanner::TokenType) -> &'static ParseRule {
+    match token_type {
+        scanner::TokenType::TokenLeftParen => {
+            return &ParseRule {
+                prefix: Some(Parser::grouping),
+                infix: Some(Parser::call),
+                precedence: Precedence::PrecCall,
+            };
+        }
+        scanner::TokenType::TokenRightParen => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenLeftBrace => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenRightBrace => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenComma => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenDot => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::dot),
+                precedence: Precedence::PrecCall,
+            };
+        }
+        scanner::TokenType::TokenMinus => {
+            return &ParseRule {
+                prefix: Some(Parser::unary),
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecTerm,
+            };
+        }
+        scanner::TokenType::TokenPlus => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecTerm,
+            };
+        }
+        scanner::TokenType::TokenSemicolon => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenColon => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenSlash => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecFactor,
+            };
+        }
+        scanner::TokenType::TokenStar => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecFactor,
+            };
+        }
+        scanner::TokenType::TokenBang => {
+            return &ParseRule {
+                prefix: Some(Parser::unary),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenBangEqual => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecEquality,
+            };
+        }
+        scanner::TokenType::TokenEqual => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenEqualEqual => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecEquality,
+            };
+        }
+        scanner::TokenType::TokenGreater => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecComparison,
+            };
+        }
+        scanner::TokenType::TokenGreaterEqual => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecComparison,
+            };
+        }
+        scanner::TokenType::TokenLess => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecComparison,
+            };
+        }
+        scanner::TokenType::TokenLessEqual => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::binary),
+                precedence: Precedence::PrecComparison,
+            };
+        }
+        scanner::TokenType::TokenIdentifier => {
+            return &ParseRule {
+                prefix: Some(Parser::variable),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenString => {
+            return &ParseRule {
+                prefix: Some(Parser::string),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenInterpolation => {
+            return &ParseRule {
+                prefix: Some(Parser::interpolation),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenNumber => {
+            return &ParseRule {
+                prefix: Some(Parser::number),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenAnd => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::and),
+                precedence: Precedence::PrecAnd,
+            };
+        }
+        scanner::TokenType::TokenBreak => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenClass => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenContinue => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenElse => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenFalse => {
+            return &ParseRule {
+                prefix: Some(Parser::literal),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenFor => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenFun => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenIf => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenNil => {
+            return &ParseRule {
+                prefix: Some(Parser::literal),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenOr => {
+            return &ParseRule {
+                prefix: None,
+                infix: Some(Parser::or),
+                precedence: Precedence::PrecOr,
+            };
+        }
+        scanner::TokenType::TokenPrint => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenReturn => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenSuper => {
+            return &ParseRule {
+                prefix: Some(Parser::super_),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenThis => {
+            return &ParseRule {
+                prefix: Some(Parser::this),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenTrue => {
+            return &ParseRule {
+                prefix: Some(Parser::literal),
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenVar => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenWhile => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenError => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+        scanner::TokenType::TokenEof => {
+            return &ParseRule {
+                prefix: None,
+                infix: None,
+                precedence: Precedence::PrecNone,
+            };
+        }
+    }
+}
+
+/// A class body being compiled. Kept on the parser so it is visible from the
+/// methods' own compilers.
+#[derive(Clone, Debug)]
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+#[derive(Clone, Debug)]
 pub struct Parser {
+    source: std::rc::Rc<str>,
     current: scanner::Token,
     previous: scanner::Token,
     had_error: bool,
     panic_mode: bool,
+    classes: Vec<ClassCompiler>,
+    diagnostics: Vec<diagnostic::Diagnostic>,
+    options: CompileOptions,
 }
 
 impl Parser {
-    pub fn new() -> Parser {
+    pub fn new(source: std::rc::Rc<str>, options: CompileOptions) -> Parser {
         return Parser {
+            source,
             current: scanner::Token::new(),
             previous: scanner::Token::new(),
             had_error: false,
             panic_mode: false,
+            classes: Vec::new(),
+            diagnostics: Vec::new(),
+            options,
         };
     }
 
-    pub fn none(
+    pub fn unary(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        _can_assign: bool,
     ) {
-        println!("NULL");
+        let operator: scanner::Token = self.previous;
+        self.parse_precedence(vm, Precedence::PrecUnary, compiler, scanner);
+        match operator.get_type() {
+            scanner::TokenType::TokenBang => {
+                compiler.emit_byte_at(chunk::OpCode::OpNot as u8, operator);
+            }
+            scanner::TokenType::TokenMinus => {
+                compiler.emit_byte_at(chunk::OpCode::OpNegate as u8, operator);
+            }
+            _ => {}
+        }
     }
-    pub fn unary(
+    pub fn binary(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        _can_assign: bool,
     ) {
-        todo!();
+        let operator: scanner::Token = self.previous;
+        let operator_type = operator.get_type();
+        let rule: &ParseRule = get_rule(operator_type);
+        self.parse_precedence(vm, rule.precedence.next(), compiler, scanner);
+        match operator_type {
+            scanner::TokenType::TokenBangEqual => {
+                compiler.emit_byte_at(chunk::OpCode::OpEqual as u8, operator);
+                compiler.emit_byte_at(chunk::OpCode::OpNot as u8, operator);
+            }
+            scanner::TokenType::TokenEqualEqual => {
+                compiler.emit_byte_at(chunk::OpCode::OpEqual as u8, operator);
+            }
+            scanner::TokenType::TokenGreater => {
+                compiler.emit_byte_at(chunk::OpCode::OpGreater as u8, operator);
+            }
+            scanner::TokenType::TokenGreaterEqual => {
+                compiler.emit_byte_at(chunk::OpCode::OpLess as u8, operator);
+                compiler.emit_byte_at(chunk::OpCode::OpNot as u8, operator);
+            }
+            scanner::TokenType::TokenLess => {
+                compiler.emit_byte_at(chunk::OpCode::OpLess as u8, operator);
+            }
+            scanner::TokenType::TokenLessEqual => {
+                compiler.emit_byte_at(chunk::OpCode::OpGreater as u8, operator);
+                compiler.emit_byte_at(chunk::OpCode::OpNot as u8, operator);
+            }
+            scanner::TokenType::TokenPlus => {
+                compiler.emit_byte_at(chunk::OpCode::OpAdd as u8, operator);
+            }
+            scanner::TokenType::TokenMinus => {
+                compiler.emit_byte_at(chunk::OpCode::OpSubtract as u8, operator);
+            }
+            scanner::TokenType::TokenStar => {
+                compiler.emit_byte_at(chunk::OpCode::OpMultiply as u8, operator);
+            }
+            scanner::TokenType::TokenSlash => {
+                compiler.emit_byte_at(chunk::OpCode::OpDivide as u8, operator);
+            }
+            _ => {}
+        }
     }
-    pub fn binary(
+    /// Skips the right operand when the left one is falsey, leaving the left
+    /// operand as the result.
+    pub fn and(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        _can_assign: bool,
     ) {
-        let operator_type = self.previous.get_type();
-        match rules.get(&self.previous.get_type()) {
-            Some(rule) => {
-                self.parse_precedence(vm, rule.precedence, compiler, table, scanner, rules);
-                match operator_type {
-                    scanner::TokenType::TokenBangEqual => {
-                        compiler.emit_bytes(
-                            chunk::OpCode::OpEqual as u8,
-                            chunk::OpCode::OpNot as u8,
-                            self,
-                        );
-                    }
-                    scanner::TokenType::TokenEqualEqual => {
-                        compiler.emit_byte(chunk::OpCode::OpEqual as u8, self);
-                    }
-                    scanner::TokenType::TokenGreater => {
-                        compiler.emit_byte(chunk::OpCode::OpGreater as u8, self);
-                    }
-                    scanner::TokenType::TokenGreaterEqual => {
-                        compiler.emit_bytes(
-                            chunk::OpCode::OpLess as u8,
-                            chunk::OpCode::OpNot as u8,
-                            self,
-                        );
-                    }
-                    scanner::TokenType::TokenLess => {
-                        compiler.emit_byte(chunk::OpCode::OpLess as u8, self);
-                    }
-                    scanner::TokenType::TokenLessEqual => {
-                        compiler.emit_bytes(
-                            chunk::OpCode::OpGreater as u8,
-                            chunk::OpCode::OpNot as u8,
-                            self,
-                        );
-                    }
-                    scanner::TokenType::TokenPlus => {
-                        compiler.emit_byte(chunk::OpCode::OpAdd as u8, self);
-                    }
-                    scanner::TokenType::TokenMinus => {
-                        compiler.emit_byte(chunk::OpCode::OpSubtract as u8, self);
-                    }
-                    scanner::TokenType::TokenStar => {
-                        compiler.emit_byte(chunk::OpCode::OpMultiply as u8, self);
-                    }
-                    scanner::TokenType::TokenSlash => {
-                        compiler.emit_byte(chunk::OpCode::OpDivide as u8, self);
-                    }
-                    _ => todo!(),
-                }
-            }
-            None => todo!(),
-        }
+        let end_jump: usize = compiler.emit_jump(chunk::OpCode::OpJumpIfFalse as u8, self);
+
+        compiler.emit_byte(chunk::OpCode::OpPop as u8, self);
+        self.parse_precedence(vm, Precedence::PrecAnd, compiler, scanner);
+
+        compiler.patch_jump(end_jump, self);
+    }
+    /// Skips the right operand when the left one is truthy, leaving the left
+    /// operand as the result.
+    pub fn or(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        let else_jump: usize = compiler.emit_jump(chunk::OpCode::OpJumpIfFalse as u8, self);
+        let end_jump: usize = compiler.emit_jump(chunk::OpCode::OpJump as u8, self);
+
+        compiler.patch_jump(else_jump, self);
+        compiler.emit_byte(chunk::OpCode::OpPop as u8, self);
+
+        self.parse_precedence(vm, Precedence::PrecOr, compiler, scanner);
+        compiler.patch_jump(end_jump, self);
+    }
+    pub fn call(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        let paren: scanner::Token = self.previous;
+        let arg_count: u8 = self.argument_list(vm, compiler, scanner);
+        compiler.emit_bytes_at(chunk::OpCode::OpCall as u8, arg_count, paren);
     }
     pub fn string(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        _can_assign: bool,
     ) {
-        todo!();
+        let chars: String = scanner.string_value(&self.previous);
+        let value: value::Value = value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(object::copy_string(vm, &chars)),
+        );
+        compiler.emit_constant(value, self);
     }
-    pub fn literal(
+    pub fn interpolation(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
         can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
     ) {
-        todo!();
+        self.string(vm, compiler, scanner, can_assign);
+        loop {
+            self.parse_precedence(vm, Precedence::PrecAssignment, compiler, scanner);
+            compiler.emit_bytes(
+                chunk::OpCode::OpStringify as u8,
+                chunk::OpCode::OpAdd as u8,
+                self,
+            );
+
+            if self.match_to(scanner, scanner::TokenType::TokenInterpolation) {
+                self.string(vm, compiler, scanner, can_assign);
+                compiler.emit_byte(chunk::OpCode::OpAdd as u8, self);
+                continue;
+            }
+
+            self.consume(
+                scanner,
+                scanner::TokenType::TokenString,
+                "Expect end of string interpolation".to_string(),
+            );
+            if self.previous.get_type() == scanner::TokenType::TokenString {
+                self.string(vm, compiler, scanner, can_assign);
+                compiler.emit_byte(chunk::OpCode::OpAdd as u8, self);
+            }
+            return;
+        }
+    }
+    pub fn literal(
+        &mut self,
+        _vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        _scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        match self.previous.get_type() {
+            scanner::TokenType::TokenFalse => {
+                compiler.emit_byte(chunk::OpCode::OpFalse as u8, self);
+            }
+            scanner::TokenType::TokenNil => {
+                compiler.emit_byte(chunk::OpCode::OpNil as u8, self);
+            }
+            scanner::TokenType::TokenTrue => {
+                compiler.emit_byte(chunk::OpCode::OpTrue as u8, self);
+            }
+            _ => {}
+        }
     }
     pub fn variable(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
         can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
     ) {
-        let name: scanner::Token = self.previous;
-        let get_op: u8;
-        let set_op: u8;
+        self.named_variable(vm, compiler, scanner, self.previous, can_assign);
+    }
+    pub fn this(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        if self.classes.is_empty() {
+            self.error_at(
+                self.previous,
+                diagnostic::Code::InvalidClassContext,
+                "Can't use 'this' outside of a class".to_string(),
+            );
+            return;
+        }
+        self.variable(vm, compiler, scanner, false);
+    }
+    pub fn super_(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        match self.classes.last() {
+            None => {
+                self.error_at(
+                    self.previous,
+                    diagnostic::Code::InvalidClassContext,
+                    "Can't use 'super' outside of a class".to_string(),
+                );
+            }
+            Some(class) if !class.has_superclass => {
+                self.error_at(
+                    self.previous,
+                    diagnostic::Code::InvalidClassContext,
+                    "Can't use 'super' in a class with no superclass".to_string(),
+                );
+            }
+            Some(_) => {}
+        }
 
-        let mut arg: u8 = compiler.resolve_local(name, self);
+        self.consume(
+            scanner,
+            scanner::TokenType::TokenDot,
+            "Expect '.' after 'super'".to_string(),
+        );
+        self.consume(
+            scanner,
+            scanner::TokenType::TokenIdentifier,
+            "Expect superclass method name".to_string(),
+        );
+        let name: usize = compiler.identifier_constant(vm, scanner, self.previous, self);
 
-        if arg != u8::MAX {
-            get_op = chunk::OpCode::OpGetLocal as u8;
-            set_op = chunk::OpCode::OpSetLocal as u8;
+        let this: scanner::Token = scanner::Token::create(scanner::TokenType::TokenThis, 0, 0, 0, 0);
+        let super_: scanner::Token =
+            scanner::Token::create(scanner::TokenType::TokenSuper, 0, 0, 0, 0);
+        self.named_variable(vm, compiler, scanner, this, false);
+        if self.match_to(scanner, scanner::TokenType::TokenLeftParen) {
+            let arg_count: u8 = self.argument_list(vm, compiler, scanner);
+            self.named_variable(vm, compiler, scanner, super_, false);
+            compiler.emit_constant_op(chunk::OpCode::OpSuperInvoke, name, self);
+            compiler.emit_byte(arg_count, self);
         } else {
-            println!("NAME: {:?}", name);
-            let value: value::Value = value::Value::create(
-                value::ValueType::ValObj,
-                value::Union::create_obj(object::copy_string(vm, table, name.get_start(), name.get_length())),
-            );
-            arg = compiler.make_constant(value, self);
-            get_op = chunk::OpCode::OpGetGlobal as u8;
-            set_op = chunk::OpCode::OpSetGlobal as u8;
+            self.named_variable(vm, compiler, scanner, super_, false);
+            compiler.emit_constant_op(chunk::OpCode::OpGetSuper, name, self);
         }
+    }
+    pub fn dot(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        can_assign: bool,
+    ) {
+        self.consume(
+            scanner,
+            scanner::TokenType::TokenIdentifier,
+            "Expect property name after '.'".to_string(),
+        );
+        let name: usize = compiler.identifier_constant(vm, scanner, self.previous, self);
 
         if can_assign && self.match_to(scanner, scanner::TokenType::TokenEqual) {
-            compiler.expression_statement(vm, self, table, scanner, rules);
-            compiler.emit_bytes(chunk::OpCode::OpSetGlobal as u8, arg, self);
+            self.parse_precedence(vm, Precedence::PrecAssignment, compiler, scanner);
+            compiler.emit_constant_op(chunk::OpCode::OpSetProperty, name, self);
+        } else if self.match_to(scanner, scanner::TokenType::TokenLeftParen) {
+            let arg_count: u8 = self.argument_list(vm, compiler, scanner);
+            compiler.emit_constant_op(chunk::OpCode::OpInvoke, name, self);
+            compiler.emit_byte(arg_count, self);
         } else {
-            compiler.emit_bytes(chunk::OpCode::OpGetGlobal as u8, arg, self);
+            compiler.emit_constant_op(chunk::OpCode::OpGetProperty, name, self);
         }
     }
-    pub fn grouping(
+
+    fn named_variable(
         &mut self,
         vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
+        name: scanner::Token,
         can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
     ) {
-        todo!();
+        let get_op: chunk::OpCode;
+        let set_op: chunk::OpCode;
+        let arg: usize;
+        let global: bool;
+
+        if let Some(slot) = compiler.resolve_local(scanner, name, self) {
+            arg = slot as usize;
+            get_op = chunk::OpCode::OpGetLocal;
+            set_op = chunk::OpCode::OpSetLocal;
+            global = false;
+        } else if let Some(upvalue) = compiler.resolve_upvalue(scanner, name, self) {
+            arg = upvalue as usize;
+            get_op = chunk::OpCode::OpGetUpvalue;
+            set_op = chunk::OpCode::OpSetUpvalue;
+            global = false;
+        } else {
+            arg = compiler.identifier_constant(vm, scanner, name, self);
+            get_op = chunk::OpCode::OpGetGlobal;
+            set_op = chunk::OpCode::OpSetGlobal;
+            global = true;
+        }
+
+        let op: chunk::OpCode = if can_assign && self.match_to(scanner, scanner::TokenType::TokenEqual) {
+            self.parse_precedence(vm, Precedence::PrecAssignment, compiler, scanner);
+            set_op
+        } else {
+            get_op
+        };
+
+        // Only a global's operand is a constant index; slots and upvalue
+        // indexes always fit in a byte and have no long form.
+        if global {
+            compiler.emit_constant_op(op, arg, self);
+        } else {
+            compiler.emit_bytes(op as u8, arg as u8, self);
+        }
+    }
+    pub fn grouping(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+        _can_assign: bool,
+    ) {
+        let open: scanner::Token = self.previous;
+        self.parse_precedence(vm, Precedence::PrecAssignment, compiler, scanner);
+        self.consume_closing(
+            scanner,
+            scanner::TokenType::TokenRightParen,
+            "Expect ')' after expression".to_string(),
+            open,
+        );
     }
     pub fn number(
         &mut self,
-        vm: &mut vm::VM,
+        _vm: &mut vm::VM,
         compiler: &mut Compiler,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        can_assign: bool,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        _can_assign: bool,
     ) {
-        let len = self
-            .previous
-            .get_start()
-            .into_iter()
-            .collect::<String>()
-            .len();
-        let t = self
-            .previous
-            .get_start()
-            .into_iter()
-            .collect::<String>()
-            .trim()
-            .trim_end_matches('\0')
-            .parse::<i64>();
-        match t {
-            Ok(value) => {
+        match scanner.number_value(&self.previous) {
+            Some(value) => {
                 let v: value::Value = value::Value::create(
                     value::ValueType::ValNumber,
                     value::Union::create_num(value),
                 );
                 compiler.emit_constant(v, self)
             }
-            Err(e) => {
-                eprintln!("{e:?}");
+            None => {
+                self.error_at(
+                    self.previous,
+                    diagnostic::Code::InvalidToken,
+                    "Number literal is too large.".to_string(),
+                );
+            }
+        }
+    }
+
+    fn argument_list(
+        &mut self,
+        vm: &mut vm::VM,
+        compiler: &mut Compiler,
+        scanner: &mut scanner::Scanner,
+    ) -> u8 {
+        let open: scanner::Token = self.previous;
+        let mut arg_count: i64 = 0;
+        if self.current.get_type() != scanner::TokenType::TokenRightParen {
+            loop {
+                self.parse_precedence(vm, Precedence::PrecAssignment, compiler, scanner);
+                if arg_count == 255 {
+                    self.error_at(
+                        self.previous,
+                        diagnostic::Code::LimitExceeded,
+                        "Can't have more than 255 arguments".to_string(),
+                    );
+                }
+                arg_count += 1;
+
+                if !self.match_to(scanner, scanner::TokenType::TokenComma) {
+                    break;
+                }
             }
         }
+        self.consume_closing(
+            scanner,
+            scanner::TokenType::TokenRightParen,
+            "Expect ')' after arguments".to_string(),
+            open,
+        );
+        return arg_count as u8;
     }
 
     pub fn consume(
         &mut self,
-        mut scanner: &mut scanner::Scanner,
+        scanner: &mut scanner::Scanner,
+        token_type: scanner::TokenType,
+        message: String,
+    ) {
+        if self.current.get_type() == token_type {
+            self.advance(scanner);
+            return;
+        }
+
+        self.error_at_current(diagnostic::Code::ExpectedToken, message);
+    }
+
+    /// Consumes the delimiter closing `open`, labelling `open` in the error
+    /// if it's missing.
+    fn consume_closing(
+        &mut self,
+        scanner: &mut scanner::Scanner,
         token_type: scanner::TokenType,
         message: String,
+        open: scanner::Token,
     ) {
         if self.current.get_type() == token_type {
-            self.advance(&mut scanner);
+            self.advance(scanner);
             return;
         }
 
-        self.error_at_current(self.current.get_start());
+        let mut diagnostic: diagnostic::Diagnostic = diagnostic::Diagnostic::error(
+            diagnostic::Code::ExpectedToken,
+            message,
+            self.span(self.current),
+        );
+        if matches!(
+            open.get_type(),
+            scanner::TokenType::TokenLeftParen | scanner::TokenType::TokenLeftBrace
+        ) {
+            diagnostic = diagnostic.with_label(
+                self.span(open),
+                format!("unclosed '{}' opened here", &self.source[open.get_range()]),
+            );
+        }
+        self.report(diagnostic);
     }
 
     pub fn parse_precedence(
@@ -267,37 +843,37 @@ impl Parser {
         vm: &mut vm::VM,
         precedence: Precedence,
         compiler: &mut Compiler,
-        table: &mut table::Table,
-        mut scanner: &mut scanner::Scanner,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        scanner: &mut scanner::Scanner,
     ) {
-        self.advance(&mut scanner);
-        match rules.get(&self.previous.get_type()) {
-            Some(previous_prefix_rule) => {
-                let can_assign = precedence <= Precedence::PrecAssignment;
-                (previous_prefix_rule.prefix)(self, vm, compiler, table, scanner, can_assign, rules);
-                match rules.get(&self.current.get_type()) {
-                    Some(current_prefix_rule) => {
-                        while precedence <= current_prefix_rule.precedence {
-                            self.advance(&mut scanner);
-                            (previous_prefix_rule.infix)(
-                                self, vm, compiler, table, scanner, can_assign, rules,
-                            );
-                        }
-                        if can_assign && self.match_to(scanner, scanner::TokenType::TokenEqual) {
-                            self.error_at(self.previous, "Invalid Assignment Target".to_string());
-                        }
-                    }
-                    None => {
-                        self.error_at(self.current, "Expect Expression".to_string());
-                        return;
-                    }
-                }
-            }
+        self.advance(scanner);
+        let prefix_rule: ParseFn = match get_rule(self.previous.get_type()).prefix {
+            Some(prefix_rule) => prefix_rule,
             None => {
-                self.error_at(self.previous, "Expect Expression".to_string());
+                self.error_at(
+                    self.previous,
+                    diagnostic::Code::ExpectedToken,
+                    "Expect Expression".to_string(),
+                );
                 return;
             }
+        };
+
+        let can_assign = precedence <= Precedence::PrecAssignment;
+        prefix_rule(self, vm, compiler, scanner, can_assign);
+
+        while precedence <= get_rule(self.current.get_type()).precedence {
+            self.advance(scanner);
+            if let Some(infix_rule) = get_rule(self.previous.get_type()).infix {
+                infix_rule(self, vm, compiler, scanner, can_assign);
+            }
+        }
+
+        if can_assign && self.match_to(scanner, scanner::TokenType::TokenEqual) {
+            self.error_at(
+                self.previous,
+                diagnostic::Code::InvalidAssignmentTarget,
+                "Invalid Assignment Target".to_string(),
+            );
         }
     }
 
@@ -320,32 +896,49 @@ impl Parser {
                 }
                 _ => {}
             }
+            self.advance(scanner);
         }
-        self.advance(scanner);
     }
 
-    fn error_at(&mut self, token: scanner::Token, message: String) {
+    /// The source span a diagnostic about `token` should point at.
+    fn span(&self, token: scanner::Token) -> diagnostic::Span {
+        // Point just past the last token rather than at the end of the file,
+        // which may be several blank lines further down.
+        let last: &str = &self.source[self.previous.get_range()];
+        if token.get_type() == scanner::TokenType::TokenEof
+            && !last.is_empty()
+            && !last.contains('\n')
+        {
+            let end: usize = self.previous.get_range().end;
+            return diagnostic::Span::create(
+                end..end,
+                self.previous.get_line(),
+                self.previous.get_column() + last.chars().count() as i64,
+            );
+        }
+        return diagnostic::Span::create(token.get_range(), token.get_line(), token.get_column());
+    }
+
+    /// Records an error. Errors are suppressed while in panic mode, until
+    /// `syncronize` finds a statement boundary.
+    fn report(&mut self, diagnostic: diagnostic::Diagnostic) {
         if self.panic_mode {
             return;
         }
         self.panic_mode = true;
-        print!("[line {}] Error", token.get_line());
-
-        if token.get_type() == scanner::TokenType::TokenEof {
-            println!(" at end: {:?}", message);
-        } else if token.get_type() == scanner::TokenType::TokenError {
-        } else {
-            println!(" at {:?}: {:?}", token.get_length(), message);
-        }
-
         self.had_error = true;
+        self.diagnostics.push(diagnostic);
     }
 
-    fn error_at_current(&mut self, message: [char; 256]) {
-        self.error_at(self.current, message.into_iter().collect::<String>());
+    fn error_at(&mut self, token: scanner::Token, code: diagnostic::Code, message: String) {
+        self.report(diagnostic::Diagnostic::error(code, message, self.span(token)));
     }
 
-    pub fn advance(&mut self, mut scanner: &mut scanner::Scanner) {
+    fn error_at_current(&mut self, code: diagnostic::Code, message: String) {
+        self.error_at(self.current, code, message);
+    }
+
+    pub fn advance(&mut self, scanner: &mut scanner::Scanner) {
         self.previous = self.current;
         loop {
             let x = scanner.scan_token();
@@ -353,7 +946,10 @@ impl Parser {
             if self.current.get_type() != scanner::TokenType::TokenError {
                 break;
             }
-            self.error_at_current(self.current.get_start());
+            self.error_at_current(
+                diagnostic::Code::InvalidToken,
+                self.current.get_message().to_string(),
+            );
         }
     }
 
@@ -363,68 +959,190 @@ impl Parser {
 
     pub fn match_to(
         &mut self,
-        mut scanner: &mut scanner::Scanner,
+        scanner: &mut scanner::Scanner,
         token_type: scanner::TokenType,
     ) -> bool {
         if !(self.current.get_type() == token_type) {
             return false;
         }
-        self.advance(&mut scanner);
+        self.advance(scanner);
         return true;
     }
 }
 
+/// The number of distinct values a one-byte operand can address.
+const UINT8_COUNT: i64 = u8::MAX as i64 + 1;
+
 #[derive(Debug)]
 struct Local {
     name: scanner::Token,
     depth: i64,
+    is_captured: bool,
+}
+
+/// A variable captured from an enclosing function: either a local slot of
+/// the directly enclosing function, or one of its own upvalues.
+#[derive(Copy, Clone, Debug)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/// A loop being compiled, so `break` and `continue` know where to jump and
+/// which locals to discard first.
+struct Loop {
+    label: Option<scanner::Token>,
+    start: usize,
+    scope_depth: i64,
+    breaks: Vec<usize>,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FunctionType {
+    TypeFunction,
+    TypeInitializer,
+    TypeMethod,
+    TypeScript,
+}
+
+/// Compiles one function body. Each nested function declaration gets its own
+/// compiler, which keeps the compiler of the surrounding function in
+/// `enclosing` until the body is finished.
 pub struct Compiler {
+    enclosing: Option<Box<Compiler>>,
+    function_type: FunctionType,
+    name: Option<std::rc::Rc<object::ObjString>>,
+    arity: i64,
     locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    loops: Vec<Loop>,
     local_count: i64,
     scope_depth: i64,
     current_chunk: chunk::Chunk,
 }
 
+impl Default for Compiler {
+    fn default() -> Compiler {
+        return Compiler::new();
+    }
+}
+
 impl Compiler {
     pub fn new() -> Compiler {
+        return Compiler::create(FunctionType::TypeScript, None);
+    }
+
+    pub fn create(
+        function_type: FunctionType,
+        name: Option<std::rc::Rc<object::ObjString>>,
+    ) -> Compiler {
+        // Slot zero holds the function being called, or the receiver in
+        // methods. Only methods let code name it, as `this`.
+        let slot_zero: scanner::Token = match function_type {
+            FunctionType::TypeMethod | FunctionType::TypeInitializer => {
+                scanner::Token::create(scanner::TokenType::TokenThis, 0, 0, 0, 0)
+            }
+            _ => scanner::Token::new(),
+        };
+
         return Compiler {
-            locals: Vec::new(),
-            local_count: 0,
+            enclosing: None,
+            function_type,
+            name,
+            arity: 0,
+            locals: vec![Local {
+                name: slot_zero,
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            loops: Vec::new(),
+            local_count: 1,
             scope_depth: 0,
             current_chunk: chunk::Chunk::new(),
         };
     }
 
-    fn resolve_local(&self, name: scanner::Token, parser: &mut Parser) -> u8 {
-        for i in (self.local_count - 1)..0 {
-            if i != -1 {
-                let local: &Local = &self.locals[i as usize];
-                if self.identifiers_equal(name, local.name) {
-                    if local.depth == -1 {
-                        parser.error_at(
-                            parser.current,
-                            "Cannot read local variable in its own initializer".to_string(),
-                        );
-                    }
-                    return i as u8;
+    /// Returns the stack slot of the innermost local named `name`, or `None`
+    /// if it has to be looked up as a global.
+    fn resolve_local(
+        &self,
+        scanner: &scanner::Scanner,
+        name: scanner::Token,
+        parser: &mut Parser,
+    ) -> Option<u8> {
+        for i in (0..self.local_count).rev() {
+            let local: &Local = &self.locals[i as usize];
+            if self.identifiers_equal(scanner, name, local.name) {
+                if local.depth == -1 {
+                    parser.error_at(
+                        name,
+                        diagnostic::Code::InvalidVariable,
+                        "Can't read local variable in its own initializer".to_string(),
+                    );
                 }
+                return Some(i as u8);
             }
         }
-        return u8::MAX;
+        return None;
     }
 
-    fn print_statement(
+    /// Resolves `name` as a variable captured from an enclosing function,
+    /// threading the capture through every function in between.
+    fn resolve_upvalue(
         &mut self,
-        vm: &mut vm::VM,
-        scanner: &mut scanner::Scanner,
-        table: &mut table::Table,
-        mut parser: &mut Parser,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
-    ) {
-        parser.parse_precedence(vm, Precedence::PrecAssignment, self, table, scanner, rules);
-        parser.consume(
+        scanner: &scanner::Scanner,
+        name: scanner::Token,
+        parser: &mut Parser,
+    ) -> Option<u8> {
+        let enclosing: &mut Compiler = self.enclosing.as_deref_mut()?;
+
+        if let Some(local) = enclosing.resolve_local(scanner, name, parser) {
+            enclosing.locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(local, true, name, parser));
+        }
+
+        if let Some(upvalue) = enclosing.resolve_upvalue(scanner, name, parser) {
+            return Some(self.add_upvalue(upvalue, false, name, parser));
+        }
+
+        return None;
+    }
+
+    fn add_upvalue(
+        &mut self,
+        index: u8,
+        is_local: bool,
+        name: scanner::Token,
+        parser: &mut Parser,
+    ) -> u8 {
+        for (i, upvalue) in self.upvalues.iter().enumerate() {
+            if upvalue.index == index && upvalue.is_local == is_local {
+                return i as u8;
+            }
+        }
+
+        if self.upvalues.len() as i64 == UINT8_COUNT {
+            parser.error_at(
+                name,
+                diagnostic::Code::LimitExceeded,
+                "Too many closure variables in function".to_string(),
+            );
+            return 0;
+        }
+
+        self.upvalues.push(Upvalue { index, is_local });
+        return (self.upvalues.len() - 1) as u8;
+    }
+
+    fn print_statement(
+        &mut self,
+        vm: &mut vm::VM,
+        scanner: &mut scanner::Scanner,
+        parser: &mut Parser,
+    ) {
+        parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+        parser.consume(
             scanner,
             scanner::TokenType::TokenSemicolon,
             "Expect ';' after value".to_string(),
@@ -432,121 +1150,674 @@ impl Compiler {
         self.emit_byte(chunk::OpCode::OpPrint as u8, parser);
     }
 
-    fn expression_statement(
+    fn return_statement(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+    ) {
+        if self.function_type == FunctionType::TypeScript {
+            parser.error_at(
+                parser.previous,
+                diagnostic::Code::InvalidReturn,
+                "Can't return from top-level code".to_string(),
+            );
+        }
+
+        if parser.match_to(scanner, scanner::TokenType::TokenSemicolon) {
+            self.emit_return(parser);
+        } else {
+            if self.function_type == FunctionType::TypeInitializer {
+                parser.error_at(
+                    parser.previous,
+                    diagnostic::Code::InvalidReturn,
+                    "Can't return a value from an initializer".to_string(),
+                );
+            }
+
+            parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+            parser.consume(
+                scanner,
+                scanner::TokenType::TokenSemicolon,
+                "Expect ';' after return value".to_string(),
+            );
+            self.emit_byte(chunk::OpCode::OpReturn as u8, parser);
+        }
+    }
+
+    fn if_statement(&mut self, vm: &mut vm::VM, parser: &mut Parser, scanner: &mut scanner::Scanner) {
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenLeftParen,
+            "Expect '(' after 'if'".to_string(),
+        );
+        parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenRightParen,
+            "Expect ')' after condition".to_string(),
+        );
+
+        let then_jump: usize = self.emit_jump(chunk::OpCode::OpJumpIfFalse as u8, parser);
+        self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+        self.statement(vm, parser, scanner);
+
+        let else_jump: usize = self.emit_jump(chunk::OpCode::OpJump as u8, parser);
+        self.patch_jump(then_jump, parser);
+        self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+
+        if parser.match_to(scanner, scanner::TokenType::TokenElse) {
+            self.statement(vm, parser, scanner);
+        }
+        self.patch_jump(else_jump, parser);
+    }
+
+    fn while_statement(
         &mut self,
         vm: &mut vm::VM,
-        mut parser: &mut Parser,
-        table: &mut table::Table,
+        parser: &mut Parser,
         scanner: &mut scanner::Scanner,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        label: Option<scanner::Token>,
     ) {
-        parser.parse_precedence(vm, Precedence::PrecAssignment, self, table, scanner, rules);
+        let loop_start: usize = self.current_chunk.get_count() as usize;
         parser.consume(
             scanner,
-            scanner::TokenType::TokenSemicolon,
-            "Expect ';' after expression".to_string(),
+            scanner::TokenType::TokenLeftParen,
+            "Expect '(' after 'while'".to_string(),
         );
+        parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenRightParen,
+            "Expect ')' after condition".to_string(),
+        );
+
+        let exit_jump: usize = self.emit_jump(chunk::OpCode::OpJumpIfFalse as u8, parser);
+        self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+        self.begin_loop(label, loop_start);
+        self.statement(vm, parser, scanner);
+        self.emit_loop(loop_start, parser);
 
+        self.patch_jump(exit_jump, parser);
         self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+        self.end_loop(parser);
+    }
+
+    fn for_statement(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+        label: Option<scanner::Token>,
+    ) {
+        self.begin_scope();
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenLeftParen,
+            "Expect '(' after 'for'".to_string(),
+        );
+        if parser.match_to(scanner, scanner::TokenType::TokenSemicolon) {
+            // No initializer.
+        } else if parser.match_to(scanner, scanner::TokenType::TokenVar) {
+            self.var_declaration(vm, parser, scanner);
+        } else {
+            self.expression_statement(vm, parser, scanner);
+        }
+
+        let mut loop_start: usize = self.current_chunk.get_count() as usize;
+        let mut exit_jump: Option<usize> = None;
+        if !parser.match_to(scanner, scanner::TokenType::TokenSemicolon) {
+            parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+            parser.consume(
+                scanner,
+                scanner::TokenType::TokenSemicolon,
+                "Expect ';' after loop condition".to_string(),
+            );
+
+            exit_jump = Some(self.emit_jump(chunk::OpCode::OpJumpIfFalse as u8, parser));
+            self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+        }
+
+        if !parser.match_to(scanner, scanner::TokenType::TokenRightParen) {
+            let body_jump: usize = self.emit_jump(chunk::OpCode::OpJump as u8, parser);
+            let increment_start: usize = self.current_chunk.get_count() as usize;
+            parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+            self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+            parser.consume(
+                scanner,
+                scanner::TokenType::TokenRightParen,
+                "Expect ')' after for clauses".to_string(),
+            );
+
+            self.emit_loop(loop_start, parser);
+            loop_start = increment_start;
+            self.patch_jump(body_jump, parser);
+        }
+
+        self.begin_loop(label, loop_start);
+        self.statement(vm, parser, scanner);
+        self.emit_loop(loop_start, parser);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, parser);
+            self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+        }
+        self.end_loop(parser);
+
+        self.end_scope(parser);
+    }
+
+    fn labeled_statement(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+    ) {
+        parser.advance(scanner);
+        let label: scanner::Token = parser.previous;
+        parser.advance(scanner);
+
+        if parser.match_to(scanner, scanner::TokenType::TokenWhile) {
+            self.while_statement(vm, parser, scanner, Some(label));
+        } else if parser.match_to(scanner, scanner::TokenType::TokenFor) {
+            self.for_statement(vm, parser, scanner, Some(label));
+        } else {
+            parser.error_at_current(
+                diagnostic::Code::ExpectedToken,
+                "Expect loop after label".to_string(),
+            );
+        }
+    }
+
+    fn break_statement(&mut self, parser: &mut Parser, scanner: &mut scanner::Scanner) {
+        let keyword: scanner::Token = parser.previous;
+        let mut label: Option<scanner::Token> = None;
+        if parser.match_to(scanner, scanner::TokenType::TokenIdentifier) {
+            label = Some(parser.previous);
+        }
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenSemicolon,
+            "Expect ';' after 'break'".to_string(),
+        );
+
+        if let Some(index) = self.find_loop(scanner, keyword, label, parser) {
+            self.discard_loop_locals(index, parser);
+            let jump: usize = self.emit_jump(chunk::OpCode::OpJump as u8, parser);
+            self.loops[index].breaks.push(jump);
+        }
+    }
+
+    fn continue_statement(&mut self, parser: &mut Parser, scanner: &mut scanner::Scanner) {
+        let keyword: scanner::Token = parser.previous;
+        let mut label: Option<scanner::Token> = None;
+        if parser.match_to(scanner, scanner::TokenType::TokenIdentifier) {
+            label = Some(parser.previous);
+        }
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenSemicolon,
+            "Expect ';' after 'continue'".to_string(),
+        );
+
+        if let Some(index) = self.find_loop(scanner, keyword, label, parser) {
+            self.discard_loop_locals(index, parser);
+            self.emit_loop(self.loops[index].start, parser);
+        }
+    }
+
+    fn begin_loop(&mut self, label: Option<scanner::Token>, start: usize) {
+        self.loops.push(Loop {
+            label,
+            start,
+            scope_depth: self.scope_depth,
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Patches every `break` in the innermost loop to jump to the current
+    /// offset.
+    fn end_loop(&mut self, parser: &mut Parser) {
+        if let Some(finished) = self.loops.pop() {
+            for jump in finished.breaks {
+                self.patch_jump(jump, parser);
+            }
+        }
     }
 
-    fn statement(
+    /// Finds the loop a `break` or `continue` refers to: the innermost one,
+    /// or the innermost one with a matching label.
+    fn find_loop(
+        &self,
+        scanner: &scanner::Scanner,
+        keyword: scanner::Token,
+        label: Option<scanner::Token>,
+        parser: &mut Parser,
+    ) -> Option<usize> {
+        if self.loops.is_empty() {
+            parser.error_at(
+                keyword,
+                diagnostic::Code::InvalidLoopControl,
+                format!("Can't use '{}' outside of a loop", scanner.lexeme(&keyword)),
+            );
+            return None;
+        }
+
+        let label: scanner::Token = match label {
+            Some(label) => label,
+            None => return Some(self.loops.len() - 1),
+        };
+
+        for i in (0..self.loops.len()).rev() {
+            if let Some(loop_label) = self.loops[i].label {
+                if self.identifiers_equal(scanner, label, loop_label) {
+                    return Some(i);
+                }
+            }
+        }
+
+        parser.error_at(
+            label,
+            diagnostic::Code::InvalidLoopControl,
+            format!("No enclosing loop labeled '{}'", scanner.lexeme(&label)),
+        );
+        return None;
+    }
+
+    /// Pops the locals declared inside a loop's body without forgetting them,
+    /// since the code after the jump is still in their scope.
+    fn discard_loop_locals(&mut self, index: usize, parser: &mut Parser) {
+        let depth: i64 = self.loops[index].scope_depth;
+        for i in (0..self.local_count).rev() {
+            if self.locals[i as usize].depth <= depth {
+                break;
+            }
+            if self.locals[i as usize].is_captured {
+                self.emit_byte(chunk::OpCode::OpCloseUpvalue as u8, parser);
+            } else {
+                self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+            }
+        }
+    }
+
+    fn expression_statement(
         &mut self,
         vm: &mut vm::VM,
-        mut parser: &mut Parser,
-        table: &mut table::Table,
+        parser: &mut Parser,
         scanner: &mut scanner::Scanner,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
     ) {
+        parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenSemicolon,
+            "Expect ';' after expression".to_string(),
+        );
+
+        self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+    }
+
+    fn statement(&mut self, vm: &mut vm::VM, parser: &mut Parser, scanner: &mut scanner::Scanner) {
         if parser.match_to(scanner, scanner::TokenType::TokenPrint) {
-            self.print_statement(vm, scanner, table, parser, rules);
+            self.print_statement(vm, scanner, parser);
         } else if parser.match_to(scanner, scanner::TokenType::TokenFor) {
-            todo!();
+            self.for_statement(vm, parser, scanner, None);
         } else if parser.match_to(scanner, scanner::TokenType::TokenIf) {
-            todo!();
+            self.if_statement(vm, parser, scanner);
+        } else if parser.match_to(scanner, scanner::TokenType::TokenReturn) {
+            self.return_statement(vm, parser, scanner);
         } else if parser.match_to(scanner, scanner::TokenType::TokenWhile) {
-            todo!();
+            self.while_statement(vm, parser, scanner, None);
+        } else if parser.match_to(scanner, scanner::TokenType::TokenBreak) {
+            self.break_statement(parser, scanner);
+        } else if parser.match_to(scanner, scanner::TokenType::TokenContinue) {
+            self.continue_statement(parser, scanner);
+        } else if parser.current.get_type() == scanner::TokenType::TokenIdentifier
+            && scanner.next_starts_with(':')
+        {
+            self.labeled_statement(vm, parser, scanner);
         } else if parser.match_to(scanner, scanner::TokenType::TokenLeftBrace) {
-            todo!();
+            self.begin_scope();
+            self.block(vm, parser, scanner);
+            self.end_scope(parser);
         } else {
-            self.expression_statement(vm, parser, table, scanner, rules);
+            self.expression_statement(vm, parser, scanner);
+        }
+    }
+
+    fn block(&mut self, vm: &mut vm::VM, parser: &mut Parser, scanner: &mut scanner::Scanner) {
+        let open: scanner::Token = parser.previous;
+        while parser.current.get_type() != scanner::TokenType::TokenRightBrace
+            && parser.current.get_type() != scanner::TokenType::TokenEof
+        {
+            self.declaration(vm, parser, scanner);
+        }
+
+        parser.consume_closing(
+            scanner,
+            scanner::TokenType::TokenRightBrace,
+            "Expect '}' after block".to_string(),
+            open,
+        );
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    /// Leaves the current scope, popping its locals off the stack.
+    fn end_scope(&mut self, parser: &mut Parser) {
+        self.scope_depth -= 1;
+
+        while self.local_count > 0
+            && self.locals[(self.local_count - 1) as usize].depth > self.scope_depth
+        {
+            if self.locals[(self.local_count - 1) as usize].is_captured {
+                self.emit_byte(chunk::OpCode::OpCloseUpvalue as u8, parser);
+            } else {
+                self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+            }
+            self.locals.pop();
+            self.local_count -= 1;
         }
     }
 
-    fn add_local(&self, name: scanner::Token) {
-        todo!();
+    fn add_local(&mut self, name: scanner::Token, parser: &mut Parser) {
+        if self.local_count == UINT8_COUNT {
+            parser.error_at(
+                name,
+                diagnostic::Code::LimitExceeded,
+                "Too many local variables in function".to_string(),
+            );
+            return;
+        }
+
+        self.locals.push(Local {
+            name,
+            depth: -1,
+            is_captured: false,
+        });
+        self.local_count += 1;
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.scope_depth == 0 {
+            return;
+        }
+        self.locals[(self.local_count - 1) as usize].depth = self.scope_depth;
     }
 
-    fn define_variable(&mut self, global: u8, parser: &mut Parser) {
+    fn define_variable(&mut self, global: usize, parser: &mut Parser) {
         if self.scope_depth > 0 {
-            self.locals[(self.local_count - 1) as usize].depth = self.scope_depth;
+            self.mark_initialized();
             return;
         }
-        self.emit_bytes(chunk::OpCode::OpDefineGlobal as u8, global, parser);
+        self.emit_constant_op(chunk::OpCode::OpDefineGlobal, global, parser);
     }
 
-    fn identifiers_equal(&self, a: scanner::Token, b: scanner::Token) -> bool {
+    fn identifiers_equal(
+        &self,
+        scanner: &scanner::Scanner,
+        a: scanner::Token,
+        b: scanner::Token,
+    ) -> bool {
+        // `this` and `super` name hidden locals with no source text, so they
+        // are matched by token type instead.
+        for keyword in [scanner::TokenType::TokenThis, scanner::TokenType::TokenSuper] {
+            if a.get_type() == keyword || b.get_type() == keyword {
+                return a.get_type() == b.get_type();
+            }
+        }
         if a.get_length() != b.get_length() {
             return false;
         }
-        todo!();
+        return scanner.lexeme(&a) == scanner.lexeme(&b);
     }
 
-    fn declare_variable(&self, parser: &mut Parser) {
+    fn declare_variable(&mut self, scanner: &scanner::Scanner, parser: &mut Parser) {
         if self.scope_depth == 0 {
             return;
         }
 
-        let name = parser.previous;
-        for i in (self.local_count - 1)..0 {
-            let local = &self.locals[i as usize];
+        let name: scanner::Token = parser.previous;
+        for i in (0..self.local_count).rev() {
+            let local: &Local = &self.locals[i as usize];
             if local.depth != -1 && local.depth < self.scope_depth {
                 break;
             }
 
-            if self.identifiers_equal(name, local.name) {
-                parser.error_at(
-                    parser.current,
-                    "Already a varaiable with this name in this scope.".to_string(),
-                );
+            if self.identifiers_equal(scanner, name, local.name) {
+                let diagnostic: diagnostic::Diagnostic = diagnostic::Diagnostic::error(
+                    diagnostic::Code::InvalidVariable,
+                    "Already a variable with this name in this scope".to_string(),
+                    parser.span(name),
+                )
+                .with_label(parser.span(local.name), "first declared here".to_string());
+                parser.report(diagnostic);
             }
         }
 
-        self.add_local(name);
+        self.add_local(name, parser);
     }
 
-    fn var_declaration(
+    /// Consumes a variable name and declares it. Returns the constant index
+    /// of the name for globals, and zero for locals.
+    fn parse_variable(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+        message: &str,
+    ) -> usize {
+        parser.consume(scanner, scanner::TokenType::TokenIdentifier, message.to_string());
+
+        self.declare_variable(scanner, parser);
+        if self.scope_depth > 0 {
+            return 0;
+        }
+
+        return self.identifier_constant(vm, scanner, parser.previous, parser);
+    }
+
+    /// Adds an identifier's name to the constant table, for instructions that
+    /// look variables or properties up by name.
+    fn identifier_constant(
+        &mut self,
+        vm: &mut vm::VM,
+        scanner: &scanner::Scanner,
+        name: scanner::Token,
+        parser: &mut Parser,
+    ) -> usize {
+        let value: value::Value = value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(object::copy_string(vm, scanner.lexeme(&name))),
+        );
+        return self.make_constant(value, parser);
+    }
+
+    /// Compiles a function's parameters and body with a fresh compiler and
+    /// emits the resulting function as a constant.
+    fn function(
         &mut self,
         vm: &mut vm::VM,
         parser: &mut Parser,
-        table: &mut table::Table,
         scanner: &mut scanner::Scanner,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
+        function_type: FunctionType,
     ) {
+        let name: object::Obj = object::copy_string(vm, scanner.lexeme(&parser.previous));
+        let compiler: Compiler = Compiler::create(function_type, Some(name.as_string()));
+        self.enclosing = Some(Box::new(std::mem::replace(self, compiler)));
+
+        self.begin_scope();
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenLeftParen,
+            "Expect '(' after function name".to_string(),
+        );
+        if parser.current.get_type() != scanner::TokenType::TokenRightParen {
+            loop {
+                self.arity += 1;
+                if self.arity > 255 {
+                    parser.error_at_current(
+                        diagnostic::Code::LimitExceeded,
+                        "Can't have more than 255 parameters".to_string(),
+                    );
+                }
+                let constant: usize = self.parse_variable(vm, parser, scanner, "Expect parameter name");
+                self.define_variable(constant, parser);
+
+                if !parser.match_to(scanner, scanner::TokenType::TokenComma) {
+                    break;
+                }
+            }
+        }
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenRightParen,
+            "Expect ')' after parameters".to_string(),
+        );
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenLeftBrace,
+            "Expect '{' before function body".to_string(),
+        );
+        self.block(vm, parser, scanner);
+
+        let upvalues: Vec<Upvalue> = self.upvalues.clone();
+        let function: std::rc::Rc<object::ObjFunction> = self.end_compiler(vm, parser);
+        let value: value::Value = value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(object::Obj::Function(function)),
+        );
+        let constant: usize = self.make_constant(value, parser);
+        self.emit_constant_op(chunk::OpCode::OpClosure, constant, parser);
+
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index, parser);
+        }
+    }
+
+    fn method(&mut self, vm: &mut vm::VM, parser: &mut Parser, scanner: &mut scanner::Scanner) {
         parser.consume(
             scanner,
             scanner::TokenType::TokenIdentifier,
-            "Expect variable name".to_string(),
+            "Expect method name".to_string(),
         );
-        self.declare_variable(parser);
-        let mut global: u8;
-        if self.scope_depth > 0 {
-            global = 0;
+        let constant: usize = self.identifier_constant(vm, scanner, parser.previous, parser);
+
+        let function_type: FunctionType = if scanner.lexeme(&parser.previous) == "init" {
+            FunctionType::TypeInitializer
         } else {
-            println!("PARSER: {:?}", parser.previous); 
-            let value: value::Value = value::Value::create(
-                value::ValueType::ValObj,
-                value::Union::create_obj(object::copy_string(
-                    vm,
-                    table,
-                    parser.previous.get_start(),
-                    parser.previous.get_length(),
-                )),
+            FunctionType::TypeMethod
+        };
+        self.function(vm, parser, scanner, function_type);
+        self.emit_constant_op(chunk::OpCode::OpMethod, constant, parser);
+    }
+
+    fn class_declaration(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+    ) {
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenIdentifier,
+            "Expect class name".to_string(),
+        );
+        let class_name: scanner::Token = parser.previous;
+        let name_constant: usize = self.identifier_constant(vm, scanner, class_name, parser);
+        self.declare_variable(scanner, parser);
+
+        self.emit_constant_op(chunk::OpCode::OpClass, name_constant, parser);
+        self.define_variable(name_constant, parser);
+
+        parser.classes.push(ClassCompiler {
+            has_superclass: false,
+        });
+
+        if parser.match_to(scanner, scanner::TokenType::TokenLess) {
+            parser.consume(
+                scanner,
+                scanner::TokenType::TokenIdentifier,
+                "Expect superclass name".to_string(),
+            );
+            parser.variable(vm, self, scanner, false);
+
+            if self.identifiers_equal(scanner, class_name, parser.previous) {
+                let diagnostic: diagnostic::Diagnostic = diagnostic::Diagnostic::error(
+                    diagnostic::Code::InvalidInheritance,
+                    "A class can't inherit from itself".to_string(),
+                    parser.span(parser.previous),
+                )
+                .with_label(parser.span(class_name), "class declared here".to_string());
+                parser.report(diagnostic);
+            }
+
+            // The superclass is stored in a hidden local named `super`, which
+            // methods capture like any other variable.
+            self.begin_scope();
+            self.add_local(
+                scanner::Token::create(scanner::TokenType::TokenSuper, 0, 0, 0, 0),
+                parser,
             );
-            global = self.make_constant(value, parser);
+            self.define_variable(0, parser);
+
+            parser.named_variable(vm, self, scanner, class_name, false);
+            self.emit_byte(chunk::OpCode::OpInherit as u8, parser);
+            if let Some(class) = parser.classes.last_mut() {
+                class.has_superclass = true;
+            }
         }
+
+        // Keep the class on the stack while its methods are bound to it.
+        parser.named_variable(vm, self, scanner, class_name, false);
+        parser.consume(
+            scanner,
+            scanner::TokenType::TokenLeftBrace,
+            "Expect '{' before class body".to_string(),
+        );
+        let open: scanner::Token = parser.previous;
+        while parser.current.get_type() != scanner::TokenType::TokenRightBrace
+            && parser.current.get_type() != scanner::TokenType::TokenEof
+        {
+            self.method(vm, parser, scanner);
+        }
+        parser.consume_closing(
+            scanner,
+            scanner::TokenType::TokenRightBrace,
+            "Expect '}' after class body".to_string(),
+            open,
+        );
+        self.emit_byte(chunk::OpCode::OpPop as u8, parser);
+
+        if let Some(class) = parser.classes.pop() {
+            if class.has_superclass {
+                self.end_scope(parser);
+            }
+        }
+    }
+
+    fn fun_declaration(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+    ) {
+        let global: usize = self.parse_variable(vm, parser, scanner, "Expect function name");
+        self.mark_initialized();
+        self.function(vm, parser, scanner, FunctionType::TypeFunction);
+        self.define_variable(global, parser);
+    }
+
+    fn var_declaration(
+        &mut self,
+        vm: &mut vm::VM,
+        parser: &mut Parser,
+        scanner: &mut scanner::Scanner,
+    ) {
+        let global: usize = self.parse_variable(vm, parser, scanner, "Expect variable name");
         if parser.match_to(scanner, scanner::TokenType::TokenEqual) {
-            self.expression_statement(vm, parser, table, scanner, rules);
+            parser.parse_precedence(vm, Precedence::PrecAssignment, self, scanner);
         } else {
             self.emit_byte(chunk::OpCode::OpNil as u8, parser);
         }
@@ -563,88 +1834,212 @@ impl Compiler {
     pub fn declaration(
         &mut self,
         vm: &mut vm::VM,
-        mut parser: &mut Parser,
-        table: &mut table::Table,
+        parser: &mut Parser,
         scanner: &mut scanner::Scanner,
-        rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
     ) {
-        if parser.match_to(scanner, scanner::TokenType::TokenVar) {
-            self.var_declaration(vm, parser, table, scanner, rules);
+        if parser.match_to(scanner, scanner::TokenType::TokenClass) {
+            self.class_declaration(vm, parser, scanner);
+        } else if parser.match_to(scanner, scanner::TokenType::TokenFun) {
+            self.fun_declaration(vm, parser, scanner);
+        } else if parser.match_to(scanner, scanner::TokenType::TokenVar) {
+            self.var_declaration(vm, parser, scanner);
         } else {
-            self.statement(vm, parser, table, scanner, rules);
+            self.statement(vm, parser, scanner);
         }
         if parser.panic_mode {
             parser.syncronize(scanner);
         }
     }
 
-    fn make_constant(&mut self, value: value::Value, parser: &mut Parser) -> u8 {
-        let constant = self.current_chunk.add_constant(value);
-        if constant > u8::MAX.into() {
+    fn make_constant(&mut self, value: value::Value, parser: &mut Parser) -> usize {
+        let constant: usize = self.current_chunk.add_constant(value);
+        if constant > chunk::CONSTANT_LONG_MAX {
             parser.error_at(
                 parser.current,
+                diagnostic::Code::LimitExceeded,
                 "Too many Constants in One Chunk".to_string(),
             );
             return 0;
         }
-        return constant as u8;
+        return constant;
+    }
+
+    /// Emits an instruction with its operand, switching to the long form with
+    /// a 24-bit index when the operand doesn't fit in a byte.
+    /// Emits an instruction whose operand indexes the constant pool, using
+    /// its long form once the index no longer fits in a byte.
+    fn emit_constant_op(&mut self, op: chunk::OpCode, index: usize, parser: &mut Parser) {
+        if index <= u8::MAX as usize {
+            self.emit_bytes(op as u8, index as u8, parser);
+            return;
+        }
+        self.emit_byte(op.long_form() as u8, parser);
+        self.emit_bytes(((index >> 16) & 0xff) as u8, ((index >> 8) & 0xff) as u8, parser);
+        self.emit_byte((index & 0xff) as u8, parser);
     }
 
     fn emit_bytes(&mut self, byte1: u8, byte2: u8, parser: &mut Parser) {
+        self.emit_byte(byte1, parser);
+        self.emit_byte(byte2, parser);
+    }
 
-        self.current_chunk
-            .write_chunk(byte1, parser.previous.get_line());
-        self.current_chunk
-            .write_chunk(byte2, parser.previous.get_line());
+    fn emit_bytes_at(&mut self, byte1: u8, byte2: u8, token: scanner::Token) {
+        self.emit_byte_at(byte1, token);
+        self.emit_byte_at(byte2, token);
     }
 
     fn emit_byte(&mut self, byte: u8, parser: &mut Parser) {
+        self.emit_byte_at(byte, parser.previous);
+    }
+
+    fn emit_byte_at(&mut self, byte: u8, token: scanner::Token) {
         self.current_chunk
-            .write_chunk(byte, parser.previous.get_line());
+            .write_chunk(byte, token.get_line(), token.get_column());
+    }
 
+    /// Emits a jump with a placeholder operand and returns the operand's
+    /// offset so it can be patched once the target is known.
+    fn emit_jump(&mut self, instruction: u8, parser: &mut Parser) -> usize {
+        self.emit_byte(instruction, parser);
+        self.emit_bytes(0xff, 0xff, parser);
+        return self.current_chunk.get_count() as usize - 2;
+    }
+
+    fn patch_jump(&mut self, offset: usize, parser: &mut Parser) {
+        let jump: usize = self.current_chunk.get_count() as usize - offset - 2;
+
+        if jump > u16::MAX as usize {
+            parser.error_at(
+                parser.previous,
+                diagnostic::Code::LimitExceeded,
+                "Too much code to jump over".to_string(),
+            );
+        }
+
+        self.current_chunk.set_code(offset, ((jump >> 8) & 0xff) as u8);
+        self.current_chunk.set_code(offset + 1, (jump & 0xff) as u8);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, parser: &mut Parser) {
+        self.emit_byte(chunk::OpCode::OpLoop as u8, parser);
+
+        let offset: usize = self.current_chunk.get_count() as usize - loop_start + 2;
+        if offset > u16::MAX as usize {
+            parser.error_at(
+                parser.previous,
+                diagnostic::Code::LimitExceeded,
+                "Loop body too large".to_string(),
+            );
+        }
+
+        self.emit_bytes(((offset >> 8) & 0xff) as u8, (offset & 0xff) as u8, parser);
     }
 
     fn emit_constant(&mut self, value: value::Value, parser: &mut Parser) {
-        let constant = self.make_constant(value, parser);
-        self.emit_bytes(
-            chunk::OpCode::OpConstant as u8,
-            constant,
-            parser,
-        );
+        let constant: usize = self.make_constant(value, parser);
+        self.emit_constant_op(chunk::OpCode::OpConstant, constant, parser);
     }
 
+    /// Emits an implicit return: of `nil`, or of the instance in slot zero
+    /// for initializers.
     fn emit_return(&mut self, parser: &mut Parser) {
+        if self.function_type == FunctionType::TypeInitializer {
+            self.emit_bytes(chunk::OpCode::OpGetLocal as u8, 0, parser);
+        } else {
+            self.emit_byte(chunk::OpCode::OpNil as u8, parser);
+        }
         self.emit_byte(chunk::OpCode::OpReturn as u8, parser);
     }
 
-    pub fn end_compiler(&mut self, vm: &mut vm::VM, parser: &mut Parser) {
+    /// Finishes the function being compiled and hands control back to the
+    /// enclosing compiler, if there is one.
+    pub fn end_compiler(
+        &mut self,
+        _vm: &mut vm::VM,
+        parser: &mut Parser,
+    ) -> std::rc::Rc<object::ObjFunction> {
         self.emit_return(parser);
+
+        if parser.options.get_opt_level() > 0 && !parser.get_had_error() {
+            optimizer::optimize(&mut self.current_chunk);
+        }
+
+        let function: std::rc::Rc<object::ObjFunction> =
+            std::rc::Rc::new(object::ObjFunction::create(
+                self.arity,
+                self.upvalues.len() as i64,
+                std::mem::take(&mut self.current_chunk),
+                self.name.clone(),
+            ));
+
+        #[cfg(feature = "debug_print_code")]
         if !parser.get_had_error() {
-            vm.disassemble_chunk(self.current_chunk, "code");
+            _vm.disassemble_chunk(function.get_chunk(), &function.print());
+        }
+
+        if let Some(enclosing) = self.enclosing.take() {
+            *self = *enclosing;
         }
+        return function;
+    }
+}
+
+/// The top-level function of a script that compiled without errors, ready
+/// to be run with `VM::execute`.
+pub struct CompiledFunction {
+    function: std::rc::Rc<object::ObjFunction>,
+}
+
+impl CompiledFunction {
+    pub fn get_function(&self) -> &std::rc::Rc<object::ObjFunction> {
+        return &self.function;
     }
 }
 
+/// Settings that change how a script is compiled but not what it means.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompileOptions {
+    opt_level: u8,
+}
+
+impl CompileOptions {
+    /// The defaults: the optimiser is off.
+    pub fn new() -> CompileOptions {
+        return CompileOptions { opt_level: 0 };
+    }
+
+    pub fn get_opt_level(&self) -> u8 {
+        return self.opt_level;
+    }
+
+    /// Sets how much the bytecode is optimised, from 0 (not at all) up to
+    /// `optimizer::OPT_LEVEL_MAX`.
+    pub fn set_opt_level(&mut self, opt_level: u8) {
+        self.opt_level = opt_level;
+    }
+}
+
+/// Compiles a script into the function that runs it, or every diagnostic
+/// reported if there were compile errors.
 pub fn compile(
     source: String,
     vm: &mut vm::VM,
-    chunk: &chunk::Chunk,
-    rules: &std::collections::HashMap<scanner::TokenType, ParseRule>,
-) -> bool {
+    options: CompileOptions,
+) -> Result<CompiledFunction, Vec<diagnostic::Diagnostic>> {
     let mut compiler: Compiler = Compiler::new();
-    let mut parser: &mut Parser = &mut Parser::new();
-    let mut scanner: &mut scanner::Scanner = &mut scanner::Scanner::create(source);
-    let mut table: &mut table::Table = &mut table::Table::new();
+    let source: std::rc::Rc<str> = std::rc::Rc::from(source);
+    let parser: &mut Parser = &mut Parser::new(std::rc::Rc::clone(&source), options);
+    let scanner: &mut scanner::Scanner = &mut scanner::Scanner::create(&*source);
 
-    let compiling_chunk: &chunk::Chunk = chunk;
-    
-    parser.advance(&mut scanner);
+    parser.advance(scanner);
 
     while !(parser.match_to(scanner, scanner::TokenType::TokenEof)) {
-        compiler.declaration(vm, parser, table, scanner, rules);
+        compiler.declaration(vm, parser, scanner);
     }
 
-    compiler.end_compiler(vm, parser);
-    
-    return !parser.had_error;
+    let function: std::rc::Rc<object::ObjFunction> = compiler.end_compiler(vm, parser);
+    if parser.get_had_error() {
+        return Err(std::mem::take(&mut parser.diagnostics));
+    }
+    return Ok(CompiledFunction { function });
 }
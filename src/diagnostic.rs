@@ -0,0 +1,247 @@
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diagnostics written to stderr should be coloured.
+pub fn use_color() -> bool {
+    return std::io::stderr().is_terminal();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+}
+
+impl Severity {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Severity::Error => return "error",
+        }
+    }
+}
+
+/// Broad kinds of compile error, so callers can tell them apart without
+/// matching on message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    InvalidToken,
+    ExpectedToken,
+    InvalidAssignmentTarget,
+    LimitExceeded,
+    InvalidVariable,
+    InvalidReturn,
+    InvalidClassContext,
+    InvalidLoopControl,
+    InvalidInheritance,
+}
+
+impl Code {
+    /// The stable identifier shown in brackets after the severity.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Code::InvalidToken => return "E0001",
+            Code::ExpectedToken => return "E0002",
+            Code::InvalidAssignmentTarget => return "E0003",
+            Code::LimitExceeded => return "E0004",
+            Code::InvalidVariable => return "E0005",
+            Code::InvalidReturn => return "E0006",
+            Code::InvalidClassContext => return "E0007",
+            Code::InvalidLoopControl => return "E0008",
+            Code::InvalidInheritance => return "E0009",
+        }
+    }
+}
+
+/// A region of the source: its byte range plus the 1-based line and column
+/// where it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    range: std::ops::Range<usize>,
+    line: i64,
+    column: i64,
+}
+
+impl Span {
+    pub fn create(range: std::ops::Range<usize>, line: i64, column: i64) -> Span {
+        return Span {
+            range,
+            line,
+            column,
+        };
+    }
+
+    pub fn get_range(&self) -> std::ops::Range<usize> {
+        return self.range.clone();
+    }
+
+    pub fn get_line(&self) -> i64 {
+        return self.line;
+    }
+
+    pub fn get_column(&self) -> i64 {
+        return self.column;
+    }
+}
+
+/// A secondary span with a note explaining how it relates to the error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    span: Span,
+    message: String,
+}
+
+impl Label {
+    pub fn get_span(&self) -> &Span {
+        return &self.span;
+    }
+
+    pub fn get_message(&self) -> &str {
+        return &self.message;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: Code,
+    message: String,
+    span: Span,
+    labels: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn create(severity: Severity, code: Code, message: String, span: Span) -> Diagnostic {
+        return Diagnostic {
+            severity,
+            code,
+            message,
+            span,
+            labels: Vec::new(),
+        };
+    }
+
+    pub fn error(code: Code, message: String, span: Span) -> Diagnostic {
+        return Diagnostic::create(Severity::Error, code, message, span);
+    }
+
+    /// Adds a secondary label, pointing at something related to the error.
+    pub fn with_label(mut self, span: Span, message: String) -> Diagnostic {
+        self.labels.push(Label { span, message });
+        return self;
+    }
+
+    pub fn get_severity(&self) -> Severity {
+        return self.severity;
+    }
+
+    pub fn get_code(&self) -> Code {
+        return self.code;
+    }
+
+    pub fn get_message(&self) -> &str {
+        return &self.message;
+    }
+
+    pub fn get_span(&self) -> &Span {
+        return &self.span;
+    }
+
+    pub fn get_labels(&self) -> &[Label] {
+        return &self.labels;
+    }
+
+    /// Formats the diagnostic as its message, the primary `file:line:col`,
+    /// and each source line involved with its spans underlined: `^` for the
+    /// primary span and `-` for labels.
+    pub fn render(&self, file: &str, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| -> String {
+            if color {
+                return format!("{}{}{}", style, text, RESET);
+            }
+            return text.to_string();
+        };
+        let severity_style: &str = match self.severity {
+            Severity::Error => RED,
+        };
+
+        let mut rendered: String = format!(
+            "{}{}\n",
+            paint(severity_style, &format!("{}[{}]", self.severity.get_name(), self.code.get_name())),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        // Spans that run past the end of `source` came from some other text
+        // and have nothing to underline.
+        let mut annotations: Vec<(&Span, Option<&str>)> = vec![(&self.span, None)];
+        for label in &self.labels {
+            annotations.push((&label.span, Some(label.message.as_str())));
+        }
+        annotations.retain(|(span, _)| span.range.end <= source.len());
+
+        let Some(widest) = annotations.iter().map(|(span, _)| span.line).max() else {
+            rendered.push_str(&format!("{} {}\n", paint(BLUE, "-->"), file));
+            return rendered;
+        };
+        let gutter: String = " ".repeat(widest.to_string().len());
+
+        rendered.push_str(&format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            paint(BLUE, "-->"),
+            file,
+            self.span.line,
+            self.span.column
+        ));
+        rendered.push_str(&format!("{} {}\n", gutter, paint(BLUE, "|")));
+
+        let mut lines: Vec<i64> = annotations.iter().map(|(span, _)| span.line).collect();
+        lines.sort();
+        lines.dedup();
+
+        for line in lines {
+            let Some((first, _)) = annotations.iter().find(|(span, _)| span.line == line) else {
+                continue;
+            };
+            let start: usize = first.range.start;
+            let line_start: usize = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end: usize = source[start..].find('\n').map_or(source.len(), |i| start + i);
+            let text: &str = source[line_start..line_end]
+                .trim_start_matches('\u{feff}')
+                .trim_end_matches('\r');
+
+            let number: String = line.to_string();
+            rendered.push_str(&format!(
+                "{}{} {} {}\n",
+                " ".repeat(gutter.len() - number.len()),
+                paint(BLUE, &number),
+                paint(BLUE, "|"),
+                text
+            ));
+
+            for (span, message) in annotations.iter().filter(|(span, _)| span.line == line) {
+                // Pad with the characters before the span, keeping tabs so
+                // the marks line up however wide the terminal draws them.
+                let prefix: Vec<char> = source[line_start..span.range.start].chars().collect();
+                let skip: usize = prefix.len().saturating_sub((span.column as usize).saturating_sub(1));
+                let padding: String = prefix[skip..]
+                    .iter()
+                    .map(|c| if *c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                let width: usize = source[span.range.start..span.range.end.min(line_end)]
+                    .chars()
+                    .count()
+                    .max(1);
+
+                let marks: String = match message {
+                    None => paint(severity_style, &"^".repeat(width)),
+                    Some(message) => paint(BLUE, &format!("{} {}", "-".repeat(width), message)),
+                };
+                rendered.push_str(&format!("{} {} {}{}\n", gutter, paint(BLUE, "|"), padding, marks));
+            }
+        }
+        return rendered;
+    }
+}
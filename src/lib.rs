@@ -0,0 +1,14 @@
+#![allow(clippy::needless_return, clippy::enum_variant_names)]
+
+pub mod chunk;
+pub mod compiler;
+mod debug;
+pub mod diagnostic;
+pub mod native;
+pub mod object;
+pub mod optimizer;
+pub mod scanner;
+pub mod table;
+mod unicode;
+pub mod value;
+pub mod vm;
@@ -1,186 +1,169 @@
-mod chunk;
-mod compiler;
-mod debug;
-mod object;
-mod scanner;
-mod table;
-mod value;
-mod vm;
+#![allow(clippy::needless_return)]
 
-fn repl(
+use rox::{compiler, diagnostic, optimizer, scanner, vm};
+
+/// Compiles and runs a script, printing any compile errors to stderr with
+/// `file` as the script's name.
+fn interpret(
     virtual_machine: &mut vm::VM,
-    rules: &std::collections::HashMap<scanner::TokenType, compiler::ParseRule>,
-) {
+    file: &str,
+    source: String,
+    options: compiler::CompileOptions,
+) -> vm::InterpretResult {
+    match compiler::compile(source.clone(), virtual_machine, options) {
+        Ok(compiled) => return virtual_machine.execute(compiled),
+        Err(diagnostics) => {
+            let color: bool = diagnostic::use_color();
+            for diagnostic in &diagnostics {
+                eprintln!("{}", diagnostic.render(file, &source, color));
+            }
+            return vm::InterpretResult::InterpretCompileError;
+        }
+    }
+}
+
+fn repl(virtual_machine: &mut vm::VM, options: compiler::CompileOptions) {
     loop {
         println!("->");
         let mut buffer = String::new();
-        let lines = std::io::stdin().read_line(&mut buffer);
-        match lines {
-            Ok(_) => {
-                println!("{:#?}", buffer);
-            }
+        match std::io::stdin().read_line(&mut buffer) {
+            Ok(0) => return,
+            Ok(_) => {}
             Err(e) => {
                 eprintln!("ERROR: {e:?}");
+                return;
             }
         }
-        virtual_machine.interpret(buffer, rules);
+        interpret(virtual_machine, "<repl>", buffer, options);
     }
 }
-fn run_file(
-    file: &String,
 
-    virtual_machine: &mut vm::VM,
-    rules: &std::collections::HashMap<scanner::TokenType, compiler::ParseRule>,
-) {
-    println!("{}", file);
+fn read_source(file: &String) -> String {
+    let bytes: Vec<u8> = match std::fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Could not read file \"{}\": {}.", file, e);
+            std::process::exit(66);
+        }
+    };
+
+    match String::from_utf8(bytes) {
+        Ok(source) => return source,
+        Err(e) => {
+            eprintln!(
+                "Could not read file \"{}\": invalid UTF-8 at byte {}.",
+                file,
+                e.utf8_error().valid_up_to()
+            );
+            std::process::exit(66);
+        }
+    }
+}
+
+fn run_file(file: &String, virtual_machine: &mut vm::VM, options: compiler::CompileOptions) {
+    let source: String = read_source(file);
 
-    let source: String = std::fs::read_to_string(file).expect("Error reading file");
+    match interpret(virtual_machine, file, source, options) {
+        vm::InterpretResult::InterpretOk => std::process::exit(0),
+        vm::InterpretResult::InterpretCompileError => std::process::exit(65),
+        vm::InterpretResult::InterpretRuntimeError => std::process::exit(70),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+fn dump_tokens(file: &String, json: bool) {
+    let source: String = read_source(file);
 
-    println!("{}", source);
-    
-    match virtual_machine.interpret(source, rules) {
-        vm::InterpretResult::InterpretOk => {
-            println!("EXIT 0");
-            std::process::exit(0)
-        },
-        vm::InterpretResult::InterpretCompileError => {
-            println!("EXIT 65");
-            std::process::exit(65)
-        },
-        vm::InterpretResult::InterpretRuntimeError => {
-            println!("EXIT 65");
-            std::process::exit(70)
-        },
+    for token in scanner::Scanner::create(source.as_str()) {
+        let range: std::ops::Range<usize> = token.get_range();
+        let lexeme: &str = &source[range.clone()];
+        if json {
+            let mut line: String = format!(
+                "{{\"type\":\"{:?}\",\"lexeme\":{},\"line\":{},\"column\":{},\"start\":{},\"end\":{}",
+                token.get_type(),
+                json_string(lexeme),
+                token.get_line(),
+                token.get_column(),
+                range.start,
+                range.end
+            );
+            if token.get_type() == scanner::TokenType::TokenError {
+                line.push_str(&format!(",\"message\":{}", json_string(token.get_message())));
+            }
+            line.push('}');
+            println!("{}", line);
+        } else if token.get_type() == scanner::TokenType::TokenError {
+            println!(
+                "{}:{}\t{:?}\t{:?}\t{}..{}\t{}",
+                token.get_line(),
+                token.get_column(),
+                token.get_type(),
+                lexeme,
+                range.start,
+                range.end,
+                token.get_message()
+            );
+        } else {
+            println!(
+                "{}:{}\t{:?}\t{:?}\t{}..{}",
+                token.get_line(),
+                token.get_column(),
+                token.get_type(),
+                lexeme,
+                range.start,
+                range.end
+            );
+        }
     }
 }
 
+fn usage() -> ! {
+    eprintln!("Usage: rox [--opt-level <0-{}>] [path]", optimizer::OPT_LEVEL_MAX);
+    eprintln!("       rox tokens [--json] <path>");
+    std::process::exit(64);
+}
+
 fn main() {
-    let rules: std::collections::HashMap<scanner::TokenType, compiler::ParseRule> =
-        std::collections::HashMap::from([
-            (
-                scanner::TokenType::TokenLeftParen,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::grouping,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenRightParen,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenLeftBrace,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenRightBrace,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenComma,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenDot,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenMinus,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::unary,
-                    infix: compiler::Parser::binary,
-                    precedence: compiler::Precedence::PrecTerm,
-                },
-            ),
-            (
-                scanner::TokenType::TokenPlus,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::binary,
-                    precedence: compiler::Precedence::PrecTerm,
-                },
-            ),
-            (
-                scanner::TokenType::TokenSemicolon,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenSlash,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::binary,
-                    precedence: compiler::Precedence::PrecFactor,
-                },
-            ),
-            (
-                scanner::TokenType::TokenNumber,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::number,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenPrint,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenEof,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::none,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-            (
-                scanner::TokenType::TokenIdentifier,
-                compiler::ParseRule {
-                    prefix: compiler::Parser::variable,
-                    infix: compiler::Parser::none,
-                    precedence: compiler::Precedence::PrecNone,
-                },
-            ),
-        ]);
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
 
     let virtual_machine: &mut vm::VM = &mut vm::VM::new();
+    let mut options: compiler::CompileOptions = compiler::CompileOptions::new();
+
+    if args.len() > 1 && args[1] == "--opt-level" {
+        match args.get(2).and_then(|level| level.parse::<u8>().ok()) {
+            Some(level) if level <= optimizer::OPT_LEVEL_MAX => options.set_opt_level(level),
+            _ => usage(),
+        }
+        args.drain(1..3);
+    }
 
     if args.len() == 1 {
-        repl(virtual_machine, &rules);
-    } else if args.len() == 2 {
-        run_file(&args[1], virtual_machine, &rules);
+        repl(virtual_machine, options);
+    } else if args.len() == 2 && args[1] != "tokens" {
+        run_file(&args[1], virtual_machine, options);
+    } else if args.len() == 3 && args[1] == "tokens" {
+        dump_tokens(&args[2], false);
+    } else if args.len() == 4 && args[1] == "tokens" && args[2] == "--json" {
+        dump_tokens(&args[3], true);
     } else {
-        eprintln!("Usage: rox [path]");
-        std::process::exit(64);
+        usage();
     }
     return;
 }
@@ -0,0 +1,10 @@
+use crate::*;
+
+/// Seconds elapsed since the VM was created, for timing scripts.
+pub fn clock(vm: &mut vm::VM, _args: &[value::Value]) -> Result<value::Value, vm::RuntimeError> {
+    let seconds: f64 = vm.get_start_time().elapsed().as_secs_f64();
+    return Ok(value::Value::create(
+        value::ValueType::ValNumber,
+        value::Union::create_num(seconds),
+    ));
+}
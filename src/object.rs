@@ -1,105 +1,532 @@
 use crate::*;
 
-#[derive(Copy, Clone, Default, PartialEq)]
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum ObjType {
     #[default]
     ObjectString,
+    ObjectFunction,
+    ObjectClosure,
+    ObjectClass,
+    ObjectInstance,
+    ObjectBoundMethod,
+    ObjectNative,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug)]
 pub struct ObjString {
-    obj: Obj,
     length: usize,
-    chars: [char; 256],
+    chars: String,
     hash: u32,
 }
 
 impl ObjString {
-    pub fn new() -> ObjString {
+    pub fn create(chars: String, hash: u32) -> ObjString {
         return ObjString {
-            obj: Obj::new(),
-            length: 0,
-            chars: ['\0'; 256],
-            hash: 0,
+            length: chars.len(),
+            chars,
+            hash,
         };
     }
 
-    pub fn create(obj: Obj, length: usize, chars: [char; 256], hash: u32) -> ObjString {
-        return ObjString {
-            obj: obj,
-            length: length,
-            chars: chars,
-            hash: hash,
+    pub fn get_length(&self) -> usize {
+        return self.length;
+    }
+
+    pub fn get_chars(&self) -> &str {
+        return &self.chars;
+    }
+
+    pub fn get_hash(&self) -> u32 {
+        return self.hash;
+    }
+}
+
+/// A compiled function: its bytecode and the number of parameters it takes.
+/// The top-level script is a function without a name.
+pub struct ObjFunction {
+    arity: i64,
+    upvalue_count: i64,
+    chunk: chunk::Chunk,
+    name: Option<Rc<ObjString>>,
+}
+
+impl ObjFunction {
+    pub fn create(
+        arity: i64,
+        upvalue_count: i64,
+        chunk: chunk::Chunk,
+        name: Option<Rc<ObjString>>,
+    ) -> ObjFunction {
+        return ObjFunction {
+            arity,
+            upvalue_count,
+            chunk,
+            name,
+        };
+    }
+
+    pub fn get_arity(&self) -> i64 {
+        return self.arity;
+    }
+
+    pub fn get_upvalue_count(&self) -> i64 {
+        return self.upvalue_count;
+    }
+
+    pub fn get_chunk(&self) -> &chunk::Chunk {
+        return &self.chunk;
+    }
+
+    pub fn get_name(&self) -> Option<&Rc<ObjString>> {
+        return self.name.as_ref();
+    }
+
+    pub fn print(&self) -> String {
+        match &self.name {
+            Some(name) => return format!("<fn {}>", name.get_chars()),
+            None => return "<script>".to_string(),
         }
     }
+}
 
-    pub fn copy(obj_string: ObjString) -> ObjString {
-    return ObjString {
-        obj: obj_string.obj,
-        length: obj_string.length,
-        chars: obj_string.chars,
-        hash: obj_string.hash,
+impl std::fmt::Debug for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.print());
     }
-} 
-    pub fn get_type(&self) -> ObjType {
-        return ObjType::ObjectString;
+}
+
+/// Where a captured variable currently lives. While the variable's frame is
+/// active it is a stack slot; once the frame returns the value moves into the
+/// upvalue itself, so every closure sharing it keeps seeing the same value.
+pub enum UpvalueState {
+    Open(usize),
+    Closed(value::Value),
+}
+
+pub struct ObjUpvalue {
+    state: std::cell::RefCell<UpvalueState>,
+}
+
+impl ObjUpvalue {
+    pub fn create(slot: usize) -> ObjUpvalue {
+        return ObjUpvalue {
+            state: std::cell::RefCell::new(UpvalueState::Open(slot)),
+        };
     }
-    pub fn get_obj(&self) -> Obj {
-        return self.obj;
+
+    /// The stack slot of an open upvalue, or `None` once it is closed.
+    pub fn get_slot(&self) -> Option<usize> {
+        match *self.state.borrow() {
+            UpvalueState::Open(slot) => return Some(slot),
+            UpvalueState::Closed(_) => return None,
+        }
     }
 
-    pub fn get_length(&self) -> usize {
-        return self.length;
+    /// The captured value, if the upvalue has been closed.
+    pub fn get_closed(&self) -> Option<value::Value> {
+        match &*self.state.borrow() {
+            UpvalueState::Open(_) => return None,
+            UpvalueState::Closed(value) => return Some(value.clone()),
+        }
     }
 
-    pub fn get_chars(&self) -> [char; 256] {
-        return self.chars;
-    } 
+    pub fn close(&self, value: value::Value) {
+        *self.state.borrow_mut() = UpvalueState::Closed(value);
+    }
 }
 
-#[derive(Copy, Clone, Default, PartialEq)]
-pub struct Obj {
-    obj_type: ObjType,
+/// A function together with the variables it captured when it was created.
+pub struct ObjClosure {
+    function: Rc<ObjFunction>,
+    upvalues: Vec<Rc<ObjUpvalue>>,
 }
 
-impl Obj {
-    pub fn new() -> Obj {
-        return Obj {
-            obj_type: ObjType::ObjectString,
+impl ObjClosure {
+    pub fn create(function: Rc<ObjFunction>, upvalues: Vec<Rc<ObjUpvalue>>) -> ObjClosure {
+        return ObjClosure { function, upvalues };
+    }
+
+    pub fn get_function(&self) -> &Rc<ObjFunction> {
+        return &self.function;
+    }
+
+    pub fn get_upvalues(&self) -> &[Rc<ObjUpvalue>] {
+        return &self.upvalues;
+    }
+
+    /// Moves the values captured only by this closure onto `pending`.
+    fn take_objects(&mut self, pending: &mut Vec<Obj>) {
+        for mut upvalue in self.upvalues.drain(..) {
+            let Some(upvalue) = Rc::get_mut(&mut upvalue) else {
+                continue;
+            };
+            let state: &mut UpvalueState = upvalue.state.get_mut();
+            if let UpvalueState::Closed(value) = std::mem::replace(state, UpvalueState::Open(0)) {
+                pending.extend(value.into_obj());
+            }
+        }
+    }
+}
+
+impl Drop for ObjClosure {
+    fn drop(&mut self) {
+        let mut pending: Vec<Obj> = Vec::new();
+        self.take_objects(&mut pending);
+        release(pending);
+    }
+}
+
+impl std::fmt::Debug for ObjClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.function.print());
+    }
+}
+
+/// A class: its name and the methods declared in its body.
+pub struct ObjClass {
+    name: Rc<ObjString>,
+    methods: std::cell::RefCell<table::Table>,
+}
+
+impl ObjClass {
+    pub fn create(name: Rc<ObjString>) -> ObjClass {
+        return ObjClass {
+            name,
+            methods: std::cell::RefCell::new(table::Table::new()),
         };
     }
 
-    pub fn get_type(&self) -> ObjType {
-        return self.obj_type;
+    pub fn get_name(&self) -> &Rc<ObjString> {
+        return &self.name;
+    }
+
+    pub fn get_method(&self, name: &Rc<ObjString>) -> Option<value::Value> {
+        return self.methods.borrow().table_get(name);
+    }
+
+    pub fn set_method(&self, name: Rc<ObjString>, method: value::Value) {
+        self.methods.borrow_mut().table_set(name, method);
+    }
+
+    /// Copies the superclass's methods down into this class. This runs
+    /// before the subclass's own methods are bound, so those override them.
+    pub fn inherit(&self, superclass: &ObjClass) {
+        superclass
+            .methods
+            .borrow()
+            .table_add_all(&mut self.methods.borrow_mut());
+    }
+
+    fn take_objects(&mut self, pending: &mut Vec<Obj>) {
+        let methods: Vec<value::Value> = self.methods.get_mut().table_take_values();
+        pending.extend(methods.into_iter().filter_map(value::Value::into_obj));
     }
 }
 
-fn hash_string(chars: [char; 256], length: usize) -> u32 {
-    let mut hash: u32 = 21;
-    for i in 0..length {
-        hash ^= chars[i] as u32;
-        hash = (hash * 16) as u32;
+impl Drop for ObjClass {
+    fn drop(&mut self) {
+        let mut pending: Vec<Obj> = Vec::new();
+        self.take_objects(&mut pending);
+        release(pending);
     }
-    return hash;
 }
 
-fn allocate_string(chars: [char; 256], length: usize, hash: u32) ->  ObjString {
+impl std::fmt::Debug for ObjClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.name.get_chars());
+    }
+}
 
-    let string: ObjString = object::ObjString::create(object::Obj::new(), length, chars, hash);
+/// An instance of a class, with fields that are added on first assignment.
+pub struct ObjInstance {
+    class: Rc<ObjClass>,
+    fields: std::cell::RefCell<table::Table>,
+}
 
-    return string;
+impl ObjInstance {
+    pub fn create(class: Rc<ObjClass>) -> ObjInstance {
+        return ObjInstance {
+            class,
+            fields: std::cell::RefCell::new(table::Table::new()),
+        };
+    }
+
+    pub fn get_class(&self) -> &Rc<ObjClass> {
+        return &self.class;
+    }
+
+    pub fn get_field(&self, name: &Rc<ObjString>) -> Option<value::Value> {
+        return self.fields.borrow().table_get(name);
+    }
+
+    pub fn set_field(&self, name: Rc<ObjString>, value: value::Value) {
+        self.fields.borrow_mut().table_set(name, value);
+    }
+
+    /// Moves the field values onto `pending`, along with a handle to the
+    /// class so that it is released from the worklist too if this instance
+    /// was its last user.
+    fn take_objects(&mut self, pending: &mut Vec<Obj>) {
+        pending.push(Obj::Class(Rc::clone(&self.class)));
+        let fields: Vec<value::Value> = self.fields.get_mut().table_take_values();
+        pending.extend(fields.into_iter().filter_map(value::Value::into_obj));
+    }
 }
 
-pub fn copy_string(vm: &mut vm::VM, table: &mut table::Table, chars: [char; 256], length: usize) -> ObjString {
-    let hash: u32 = hash_string(chars, length);
+impl Drop for ObjInstance {
+    fn drop(&mut self) {
+        let mut pending: Vec<Obj> = Vec::new();
+        self.take_objects(&mut pending);
+        release(pending);
+    }
+}
+
+impl std::fmt::Debug for ObjInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{} instance", self.class.get_name().get_chars());
+    }
+}
+
+/// A method closure paired with the instance it was accessed on, so `this`
+/// is bound when it is eventually called.
+pub struct ObjBoundMethod {
+    receiver: value::Value,
+    method: Rc<ObjClosure>,
+}
+
+impl ObjBoundMethod {
+    pub fn create(receiver: value::Value, method: Rc<ObjClosure>) -> ObjBoundMethod {
+        return ObjBoundMethod { receiver, method };
+    }
+
+    pub fn get_receiver(&self) -> &value::Value {
+        return &self.receiver;
+    }
 
-    let interned = table.table_find_string(chars, length, hash);
+    pub fn get_method(&self) -> &Rc<ObjClosure> {
+        return &self.method;
+    }
+
+    fn take_objects(&mut self, pending: &mut Vec<Obj>) {
+        pending.push(Obj::Closure(Rc::clone(&self.method)));
+        pending.extend(std::mem::take(&mut self.receiver).into_obj());
+    }
+}
+
+impl Drop for ObjBoundMethod {
+    fn drop(&mut self) {
+        let mut pending: Vec<Obj> = Vec::new();
+        self.take_objects(&mut pending);
+        release(pending);
+    }
+}
+
+impl std::fmt::Debug for ObjBoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.method.get_function().print());
+    }
+}
 
-    match interned {
-        Some(value) => return ObjString::copy(value),
-        None => {
-            return allocate_string(chars, length, hash);
+/// The signature of a function implemented in Rust and callable from Lox.
+pub type NativeFn = fn(&mut vm::VM, &[value::Value]) -> Result<value::Value, vm::RuntimeError>;
+
+/// A Rust function bound to a global name, with the number of arguments it
+/// expects.
+pub struct ObjNative {
+    name: Rc<ObjString>,
+    arity: i64,
+    function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn create(name: Rc<ObjString>, arity: i64, function: NativeFn) -> ObjNative {
+        return ObjNative {
+            name,
+            arity,
+            function,
+        };
+    }
+
+    pub fn get_name(&self) -> &Rc<ObjString> {
+        return &self.name;
+    }
+
+    pub fn get_arity(&self) -> i64 {
+        return self.arity;
+    }
+
+    pub fn get_function(&self) -> NativeFn {
+        return self.function;
+    }
+}
+
+impl std::fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "<native fn {}>", self.name.get_chars());
+    }
+}
+
+/// A handle to a heap object. Strings are interned, so two handles are the
+/// same object exactly when they point at the same allocation.
+#[derive(Clone, Debug)]
+pub enum Obj {
+    String(Rc<ObjString>),
+    Function(Rc<ObjFunction>),
+    Closure(Rc<ObjClosure>),
+    Class(Rc<ObjClass>),
+    Instance(Rc<ObjInstance>),
+    BoundMethod(Rc<ObjBoundMethod>),
+    Native(Rc<ObjNative>),
+}
+
+impl Obj {
+    pub fn get_type(&self) -> ObjType {
+        match self {
+            Obj::String(_) => return ObjType::ObjectString,
+            Obj::Function(_) => return ObjType::ObjectFunction,
+            Obj::Closure(_) => return ObjType::ObjectClosure,
+            Obj::Class(_) => return ObjType::ObjectClass,
+            Obj::Instance(_) => return ObjType::ObjectInstance,
+            Obj::BoundMethod(_) => return ObjType::ObjectBoundMethod,
+            Obj::Native(_) => return ObjType::ObjectNative,
         }
     }
 
+    pub fn as_string(&self) -> Rc<ObjString> {
+        match self {
+            Obj::String(string) => return Rc::clone(string),
+            _ => panic!("object is not a string"),
+        }
+    }
+
+    pub fn as_function(&self) -> Rc<ObjFunction> {
+        match self {
+            Obj::Function(function) => return Rc::clone(function),
+            _ => panic!("object is not a function"),
+        }
+    }
+
+    pub fn as_closure(&self) -> Rc<ObjClosure> {
+        match self {
+            Obj::Closure(closure) => return Rc::clone(closure),
+            _ => panic!("object is not a closure"),
+        }
+    }
+
+    pub fn as_class(&self) -> Rc<ObjClass> {
+        match self {
+            Obj::Class(class) => return Rc::clone(class),
+            _ => panic!("object is not a class"),
+        }
+    }
+
+    pub fn as_instance(&self) -> Rc<ObjInstance> {
+        match self {
+            Obj::Instance(instance) => return Rc::clone(instance),
+            _ => panic!("object is not an instance"),
+        }
+    }
+
+    pub fn print(&self) -> String {
+        match self {
+            Obj::String(string) => return string.get_chars().to_string(),
+            Obj::Function(function) => return function.print(),
+            Obj::Closure(closure) => return closure.get_function().print(),
+            Obj::Class(class) => return class.get_name().get_chars().to_string(),
+            Obj::Instance(instance) => {
+                return format!("{} instance", instance.get_class().get_name().get_chars())
+            }
+            Obj::BoundMethod(bound) => return bound.get_method().get_function().print(),
+            Obj::Native(_) => return "<native fn>".to_string(),
+        }
+    }
+}
+
+impl PartialEq for Obj {
+    fn eq(&self, other: &Obj) -> bool {
+        match (self, other) {
+            (Obj::String(a), Obj::String(b)) => return Rc::ptr_eq(a, b),
+            (Obj::Function(a), Obj::Function(b)) => return Rc::ptr_eq(a, b),
+            (Obj::Closure(a), Obj::Closure(b)) => return Rc::ptr_eq(a, b),
+            (Obj::Class(a), Obj::Class(b)) => return Rc::ptr_eq(a, b),
+            (Obj::Instance(a), Obj::Instance(b)) => return Rc::ptr_eq(a, b),
+            (Obj::BoundMethod(a), Obj::BoundMethod(b)) => return Rc::ptr_eq(a, b),
+            (Obj::Native(a), Obj::Native(b)) => return Rc::ptr_eq(a, b),
+            _ => return false,
+        }
+    }
+}
+
+/// Frees the objects in `pending` one at a time. Objects can form chains as
+/// long as a program likes, such as a linked list of instances or closures
+/// that each capture the previous one, and letting `Rc` drop them would
+/// recurse once per link until the stack overflows. So before the last
+/// handle to an object goes away, the objects it holds are moved onto the
+/// worklist instead.
+fn release(mut pending: Vec<Obj>) {
+    while let Some(mut obj) = pending.pop() {
+        match &mut obj {
+            Obj::Closure(closure) => {
+                if let Some(closure) = Rc::get_mut(closure) {
+                    closure.take_objects(&mut pending);
+                }
+            }
+            Obj::Class(class) => {
+                if let Some(class) = Rc::get_mut(class) {
+                    class.take_objects(&mut pending);
+                }
+            }
+            Obj::Instance(instance) => {
+                if let Some(instance) = Rc::get_mut(instance) {
+                    instance.take_objects(&mut pending);
+                }
+            }
+            Obj::BoundMethod(bound) => {
+                if let Some(bound) = Rc::get_mut(bound) {
+                    bound.take_objects(&mut pending);
+                }
+            }
+            Obj::String(_) | Obj::Function(_) | Obj::Native(_) => {}
+        }
+    }
+}
+
+fn hash_string(chars: &str) -> u32 {
+    let mut hash: u32 = 2166136261;
+    for byte in chars.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    return hash;
+}
+
+fn allocate_string(vm: &mut vm::VM, chars: String, hash: u32) -> Rc<ObjString> {
+    let string: Rc<ObjString> = Rc::new(ObjString::create(chars, hash));
+    vm.get_strings()
+        .table_set(Rc::clone(&string), value::Value::new());
+    return string;
+}
+
+/// Interns a copy of `chars`, reusing the existing string object if one with
+/// the same contents has already been allocated.
+pub fn copy_string(vm: &mut vm::VM, chars: &str) -> Obj {
+    let hash: u32 = hash_string(chars);
+
+    match vm.get_strings().table_find_string(chars, hash) {
+        Some(interned) => return Obj::String(interned),
+        None => return Obj::String(allocate_string(vm, chars.to_string(), hash)),
+    }
+}
+
+/// Interns `chars`, taking ownership of the buffer when no equal string
+/// exists yet.
+pub fn take_string(vm: &mut vm::VM, chars: String) -> Obj {
+    let hash: u32 = hash_string(&chars);
+
+    match vm.get_strings().table_find_string(&chars, hash) {
+        Some(interned) => return Obj::String(interned),
+        None => return Obj::String(allocate_string(vm, chars, hash)),
+    }
 }
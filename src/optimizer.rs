@@ -0,0 +1,572 @@
+use crate::*;
+
+/// Highest level accepted by `--opt-level`. Level 0 leaves the bytecode as
+/// the compiler emitted it; level 1 runs every pass in this module.
+pub const OPT_LEVEL_MAX: u8 = 1;
+
+/// One decoded instruction. Jumps refer to their target by instruction index
+/// rather than byte offset, so instructions can be removed or rewritten
+/// without breaking them.
+#[derive(Clone)]
+struct Instruction {
+    bytes: Vec<u8>,
+    line: i64,
+    column: i64,
+    target: Option<usize>,
+}
+
+impl Instruction {
+    fn create(bytes: Vec<u8>, line: i64, column: i64) -> Instruction {
+        return Instruction {
+            bytes,
+            line,
+            column,
+            target: None,
+        };
+    }
+
+    fn get_op(&self) -> chunk::OpCode {
+        return self.bytes[0].into();
+    }
+
+    fn is_jump(&self) -> bool {
+        return matches!(
+            self.get_op(),
+            chunk::OpCode::OpJump | chunk::OpCode::OpJumpIfFalse | chunk::OpCode::OpLoop
+        );
+    }
+}
+
+/// Rewrites a chunk's bytecode in place: folds constant expressions, drops
+/// dead stack traffic, threads jumps and removes unreachable code.
+pub fn optimize(chunk: &mut chunk::Chunk) {
+    let mut instructions: Vec<Instruction> = decode(chunk);
+
+    loop {
+        let mut changed: bool = fold_constants(chunk, &mut instructions);
+        changed |= thread_jumps(&mut instructions);
+        changed |= remove_unreachable(&mut instructions);
+        if !changed {
+            break;
+        }
+    }
+
+    encode(chunk, &instructions);
+}
+
+fn instruction_length(chunk: &chunk::Chunk, offset: usize) -> usize {
+    let instruction: chunk::OpCode = chunk.get_code()[offset].into();
+    match instruction {
+        chunk::OpCode::OpConstant
+        | chunk::OpCode::OpGetLocal
+        | chunk::OpCode::OpSetLocal
+        | chunk::OpCode::OpGetGlobal
+        | chunk::OpCode::OpDefineGlobal
+        | chunk::OpCode::OpSetGlobal
+        | chunk::OpCode::OpCall
+        | chunk::OpCode::OpGetUpvalue
+        | chunk::OpCode::OpSetUpvalue
+        | chunk::OpCode::OpGetProperty
+        | chunk::OpCode::OpSetProperty
+        | chunk::OpCode::OpClass
+        | chunk::OpCode::OpMethod
+        | chunk::OpCode::OpGetSuper => return 2,
+        chunk::OpCode::OpJump
+        | chunk::OpCode::OpJumpIfFalse
+        | chunk::OpCode::OpLoop
+        | chunk::OpCode::OpInvoke
+        | chunk::OpCode::OpSuperInvoke => return 3,
+        chunk::OpCode::OpConstantLong
+        | chunk::OpCode::OpGetGlobalLong
+        | chunk::OpCode::OpDefineGlobalLong
+        | chunk::OpCode::OpSetGlobalLong
+        | chunk::OpCode::OpGetPropertyLong
+        | chunk::OpCode::OpSetPropertyLong
+        | chunk::OpCode::OpClassLong
+        | chunk::OpCode::OpMethodLong
+        | chunk::OpCode::OpGetSuperLong => return 4,
+        chunk::OpCode::OpInvokeLong | chunk::OpCode::OpSuperInvokeLong => return 5,
+        chunk::OpCode::OpClosure | chunk::OpCode::OpClosureLong => {
+            let (constant, width): (usize, usize) = chunk.get_constant_operand(offset);
+            let function: std::rc::Rc<object::ObjFunction> = chunk
+                .get_constants()
+                .get_value(constant)
+                .get_value()
+                .get_obj()
+                .as_function();
+            return 1 + width + 2 * function.get_upvalue_count() as usize;
+        }
+        _ => return 1,
+    }
+}
+
+fn decode(chunk: &chunk::Chunk) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::new();
+
+    let mut offset: usize = 0;
+    while offset < chunk.get_code().len() {
+        let length: usize = instruction_length(chunk, offset);
+        instructions.push(Instruction::create(
+            chunk.get_code()[offset..offset + length].to_vec(),
+            chunk.get_lines()[offset],
+            chunk.get_columns()[offset],
+        ));
+        offsets.push(offset);
+        offset += length;
+    }
+    offsets.push(offset);
+
+    for (i, instruction) in instructions.iter_mut().enumerate() {
+        if !instruction.is_jump() {
+            continue;
+        }
+        let jump: usize = ((instruction.bytes[1] as usize) << 8) | instruction.bytes[2] as usize;
+        let after: usize = offsets[i] + 3;
+        let target: usize = if instruction.get_op() == chunk::OpCode::OpLoop {
+            after - jump
+        } else {
+            after + jump
+        };
+        instruction.target = offsets.binary_search(&target).ok();
+    }
+
+    return instructions;
+}
+
+/// Lays the instructions back out as bytes. If a threaded jump no longer fits
+/// in its 16-bit operand the chunk is left untouched.
+fn encode(chunk: &mut chunk::Chunk, instructions: &[Instruction]) {
+    let mut offsets: Vec<usize> = Vec::with_capacity(instructions.len() + 1);
+    let mut offset: usize = 0;
+    for instruction in instructions {
+        offsets.push(offset);
+        offset += instruction.bytes.len();
+    }
+    offsets.push(offset);
+
+    let mut code: Vec<u8> = Vec::with_capacity(offset);
+    let mut lines: Vec<i64> = Vec::with_capacity(offset);
+    let mut columns: Vec<i64> = Vec::with_capacity(offset);
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        let mut bytes: Vec<u8> = instruction.bytes.clone();
+        if let Some(target) = instruction.target {
+            let after: usize = offsets[i] + 3;
+            let jump: usize = if instruction.get_op() == chunk::OpCode::OpLoop {
+                after - offsets[target]
+            } else {
+                offsets[target] - after
+            };
+            if jump > u16::MAX as usize {
+                return;
+            }
+            bytes[1] = ((jump >> 8) & 0xff) as u8;
+            bytes[2] = (jump & 0xff) as u8;
+        }
+
+        for byte in bytes {
+            code.push(byte);
+            lines.push(instruction.line);
+            columns.push(instruction.column);
+        }
+    }
+
+    chunk.replace_code(code, lines, columns);
+}
+
+/// Drops the instructions flagged in `removed`. Jumps aimed at a removed
+/// instruction land on the next one that survives.
+fn compact(instructions: &mut Vec<Instruction>, removed: &[bool]) {
+    let mut new_index: Vec<usize> = Vec::with_capacity(instructions.len() + 1);
+    let mut kept: usize = 0;
+    for is_removed in removed {
+        new_index.push(kept);
+        if !is_removed {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let mut compacted: Vec<Instruction> = Vec::with_capacity(kept);
+    for (i, instruction) in instructions.drain(..).enumerate() {
+        if removed[i] {
+            continue;
+        }
+        let mut instruction: Instruction = instruction;
+        instruction.target = instruction.target.map(|target| new_index[target]);
+        compacted.push(instruction);
+    }
+    *instructions = compacted;
+}
+
+/// The value an instruction pushes, if it only ever pushes that one value.
+fn literal(chunk: &chunk::Chunk, instruction: &Instruction) -> Option<value::Value> {
+    match instruction.get_op() {
+        chunk::OpCode::OpNil => return Some(value::Value::new()),
+        chunk::OpCode::OpTrue => return Some(bool_value(true)),
+        chunk::OpCode::OpFalse => return Some(bool_value(false)),
+        chunk::OpCode::OpConstant | chunk::OpCode::OpConstantLong => {
+            let constant: usize = if instruction.get_op() == chunk::OpCode::OpConstant {
+                instruction.bytes[1] as usize
+            } else {
+                ((instruction.bytes[1] as usize) << 16)
+                    | ((instruction.bytes[2] as usize) << 8)
+                    | instruction.bytes[3] as usize
+            };
+            return Some(chunk.get_constants().get_value(constant));
+        }
+        _ => return None,
+    }
+}
+
+fn bool_value(boolean: bool) -> value::Value {
+    return value::Value::create(value::ValueType::ValBool, value::Union::create_bool(boolean));
+}
+
+fn number_value(number: f64) -> value::Value {
+    return value::Value::create(value::ValueType::ValNumber, value::Union::create_num(number));
+}
+
+fn number(value: &value::Value) -> Option<f64> {
+    if value.get_value_type() != value::ValueType::ValNumber {
+        return None;
+    }
+    return Some(value.get_value().get_number());
+}
+
+/// Builds the instruction that pushes `value`, adding it to the constant
+/// pool when it isn't a boolean.
+fn load_literal(chunk: &mut chunk::Chunk, value: value::Value, line: i64, column: i64) -> Instruction {
+    if value.get_value_type() == value::ValueType::ValBool {
+        let op: chunk::OpCode = if value.get_value().get_boolean() {
+            chunk::OpCode::OpTrue
+        } else {
+            chunk::OpCode::OpFalse
+        };
+        return Instruction::create(vec![op as u8], line, column);
+    }
+
+    let constant: usize = chunk.add_constant(value);
+    if constant <= u8::MAX as usize {
+        return Instruction::create(
+            vec![chunk::OpCode::OpConstant as u8, constant as u8],
+            line,
+            column,
+        );
+    }
+    return Instruction::create(
+        vec![
+            chunk::OpCode::OpConstantLong as u8,
+            ((constant >> 16) & 0xff) as u8,
+            ((constant >> 8) & 0xff) as u8,
+            (constant & 0xff) as u8,
+        ],
+        line,
+        column,
+    );
+}
+
+/// Evaluates a binary operator on two literals, for the cases where the
+/// result is known without running the program.
+fn fold_binary(op: chunk::OpCode, a: &value::Value, b: &value::Value) -> Option<value::Value> {
+    if op == chunk::OpCode::OpEqual {
+        return Some(bool_value(a.equal(b)));
+    }
+    if op == chunk::OpCode::OpNotEqual {
+        return Some(bool_value(!a.equal(b)));
+    }
+
+    let (a, b): (f64, f64) = (number(a)?, number(b)?);
+    match op {
+        chunk::OpCode::OpAdd => return Some(number_value(a + b)),
+        chunk::OpCode::OpSubtract => return Some(number_value(a - b)),
+        chunk::OpCode::OpMultiply => return Some(number_value(a * b)),
+        chunk::OpCode::OpDivide => return Some(number_value(a / b)),
+        chunk::OpCode::OpGreater => return Some(bool_value(a > b)),
+        chunk::OpCode::OpLess => return Some(bool_value(a < b)),
+        _ => return None,
+    }
+}
+
+/// Peephole pass over straight-line code: folds operators applied to
+/// literals, turns `OpEqual OpNot` into `OpNotEqual` and drops literals that
+/// are pushed only to be popped. Nothing is merged across a jump target.
+fn fold_constants(chunk: &mut chunk::Chunk, instructions: &mut Vec<Instruction>) -> bool {
+    let mut is_target: Vec<bool> = vec![false; instructions.len() + 1];
+    for instruction in instructions.iter() {
+        if let Some(target) = instruction.target {
+            is_target[target] = true;
+        }
+    }
+
+    let mut removed: Vec<bool> = vec![false; instructions.len()];
+    let mut changed: bool = false;
+    let mut i: usize = 0;
+    while i < instructions.len() {
+        let first: Option<value::Value> = literal(chunk, &instructions[i]);
+
+        if i + 2 < instructions.len() && !is_target[i + 1] && !is_target[i + 2] {
+            let second: Option<value::Value> = literal(chunk, &instructions[i + 1]);
+            if let (Some(a), Some(b)) = (&first, &second) {
+                if let Some(result) = fold_binary(instructions[i + 2].get_op(), a, b) {
+                    let (line, column): (i64, i64) = (instructions[i].line, instructions[i].column);
+                    instructions[i] = load_literal(chunk, result, line, column);
+                    removed[i + 1] = true;
+                    removed[i + 2] = true;
+                    changed = true;
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+
+        if i + 1 < instructions.len() && !is_target[i + 1] {
+            let next: chunk::OpCode = instructions[i + 1].get_op();
+            let (line, column): (i64, i64) = (instructions[i].line, instructions[i].column);
+
+            let folded: Option<value::Value> = match (&first, next) {
+                (Some(a), chunk::OpCode::OpNot) => Some(bool_value(a.is_falsey())),
+                (Some(a), chunk::OpCode::OpNegate) => number(a).map(|n| number_value(-n)),
+                _ => None,
+            };
+            if let Some(result) = folded {
+                instructions[i] = load_literal(chunk, result, line, column);
+                removed[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+
+            if first.is_some() && next == chunk::OpCode::OpPop {
+                removed[i] = true;
+                removed[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+
+            if instructions[i].get_op() == chunk::OpCode::OpEqual && next == chunk::OpCode::OpNot {
+                instructions[i].bytes[0] = chunk::OpCode::OpNotEqual as u8;
+                removed[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    if changed {
+        compact(instructions, &removed);
+    }
+    return changed;
+}
+
+/// Points jumps that land on another jump straight at the final target, and
+/// removes jumps to the instruction right after them.
+fn thread_jumps(instructions: &mut Vec<Instruction>) -> bool {
+    let mut changed: bool = false;
+
+    for i in 0..instructions.len() {
+        let Some(original) = instructions[i].target else {
+            continue;
+        };
+        let mut target: usize = original;
+        let conditional: bool = instructions[i].get_op() == chunk::OpCode::OpJumpIfFalse;
+
+        // A conditional jump can also skip through another `OpJumpIfFalse`:
+        // the condition is still on the stack, so it would jump again.
+        let mut hops: usize = 0;
+        while target < instructions.len() && hops < instructions.len() {
+            let next: &Instruction = &instructions[target];
+            let follows: bool = match next.get_op() {
+                chunk::OpCode::OpJump | chunk::OpCode::OpLoop => true,
+                chunk::OpCode::OpJumpIfFalse => conditional,
+                _ => false,
+            };
+            let Some(next_target) = next.target.filter(|_| follows) else {
+                break;
+            };
+            if conditional && next_target <= i {
+                break;
+            }
+            target = next_target;
+            hops += 1;
+        }
+
+        if target != original {
+            instructions[i].target = Some(target);
+            changed = true;
+        }
+        if !conditional {
+            let op: chunk::OpCode = if target > i {
+                chunk::OpCode::OpJump
+            } else {
+                chunk::OpCode::OpLoop
+            };
+            instructions[i].bytes[0] = op as u8;
+        }
+    }
+
+    let removed: Vec<bool> = instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| instruction.target == Some(i + 1))
+        .collect();
+    if removed.contains(&true) {
+        compact(instructions, &removed);
+        changed = true;
+    }
+    return changed;
+}
+
+/// Removes instructions that no path from the start of the chunk reaches,
+/// such as code after a `return`.
+fn remove_unreachable(instructions: &mut Vec<Instruction>) -> bool {
+    let mut reachable: Vec<bool> = vec![false; instructions.len()];
+    let mut pending: Vec<usize> = vec![0];
+
+    while let Some(i) = pending.pop() {
+        if i >= instructions.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+
+        let instruction: &Instruction = &instructions[i];
+        match instruction.get_op() {
+            chunk::OpCode::OpReturn => {}
+            chunk::OpCode::OpJump | chunk::OpCode::OpLoop => pending.extend(instruction.target),
+            chunk::OpCode::OpJumpIfFalse => {
+                pending.extend(instruction.target);
+                pending.push(i + 1);
+            }
+            _ => pending.push(i + 1),
+        }
+    }
+
+    if !reachable.contains(&false) {
+        return false;
+    }
+    let removed: Vec<bool> = reachable.iter().map(|reached| !reached).collect();
+    compact(instructions, &removed);
+    return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunk::OpCode::*;
+
+    fn chunk_with(code: &[u8], constants: &[f64]) -> chunk::Chunk {
+        let mut chunk: chunk::Chunk = chunk::Chunk::new();
+        for constant in constants {
+            chunk.add_constant(number_value(*constant));
+        }
+        for byte in code {
+            chunk.write_chunk(*byte, 1, 1);
+        }
+        return chunk;
+    }
+
+    #[test]
+    fn folds_constant_arithmetic() {
+        let mut chunk: chunk::Chunk = chunk_with(
+            &[
+                OpConstant as u8, 0,
+                OpConstant as u8, 0,
+                OpMultiply as u8,
+                OpConstant as u8, 1,
+                OpMultiply as u8,
+                OpReturn as u8,
+            ],
+            &[60.0, 24.0],
+        );
+        optimize(&mut chunk);
+
+        assert_eq!(chunk.get_code().len(), 3);
+        assert_eq!(chunk.get_code()[0], OpConstant as u8);
+        assert_eq!(chunk.get_code()[2], OpReturn as u8);
+        let folded: value::Value = chunk.get_constants().get_value(chunk.get_code()[1] as usize);
+        assert_eq!(number(&folded), Some(86400.0));
+    }
+
+    #[test]
+    fn rewrites_equal_not_as_not_equal() {
+        let mut chunk: chunk::Chunk = chunk_with(
+            &[
+                OpGetLocal as u8, 1,
+                OpGetLocal as u8, 2,
+                OpEqual as u8,
+                OpNot as u8,
+                OpReturn as u8,
+            ],
+            &[],
+        );
+        optimize(&mut chunk);
+
+        assert_eq!(
+            chunk.get_code(),
+            &[
+                OpGetLocal as u8, 1,
+                OpGetLocal as u8, 2,
+                OpNotEqual as u8,
+                OpReturn as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn threads_jump_to_jump() {
+        // The `OpJumpIfFalse` lands on an `OpJump` to the second print.
+        let mut chunk: chunk::Chunk = chunk_with(
+            &[
+                OpGetLocal as u8, 1,
+                OpJumpIfFalse as u8, 0, 4,
+                OpGetLocal as u8, 2,
+                OpPrint as u8,
+                OpReturn as u8,
+                OpJump as u8, 0, 1,
+                OpNil as u8,
+                OpGetLocal as u8, 3,
+                OpPrint as u8,
+                OpReturn as u8,
+            ],
+            &[],
+        );
+        optimize(&mut chunk);
+
+        assert_eq!(
+            chunk.get_code(),
+            &[
+                OpGetLocal as u8, 1,
+                OpJumpIfFalse as u8, 0, 4,
+                OpGetLocal as u8, 2,
+                OpPrint as u8,
+                OpReturn as u8,
+                OpGetLocal as u8, 3,
+                OpPrint as u8,
+                OpReturn as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_code_after_return() {
+        let mut chunk: chunk::Chunk = chunk_with(
+            &[
+                OpNil as u8,
+                OpReturn as u8,
+                OpConstant as u8, 0,
+                OpPrint as u8,
+                OpNil as u8,
+                OpReturn as u8,
+            ],
+            &[1.0],
+        );
+        optimize(&mut chunk);
+
+        assert_eq!(chunk.get_code(), &[OpNil as u8, OpReturn as u8]);
+    }
+}
@@ -11,6 +11,7 @@ pub enum TokenType {
     TokenMinus,
     TokenPlus,
     TokenSemicolon,
+    TokenColon,
     TokenSlash,
     TokenStar,
     TokenBang,
@@ -23,9 +24,12 @@ pub enum TokenType {
     TokenLessEqual,
     TokenIdentifier,
     TokenString,
+    TokenInterpolation,
     TokenNumber,
     TokenAnd,
+    TokenBreak,
     TokenClass,
+    TokenContinue,
     TokenElse,
     TokenFalse,
     TokenFor,
@@ -47,27 +51,66 @@ pub enum TokenType {
 #[derive(Copy, Clone, Debug)]
 pub struct Token {
     token_type: TokenType,
-    start: [char; 256],
+    start: usize,
     length: usize,
     line: i64,
+    column: i64,
+    message: &'static str,
+    doc: Option<(usize, usize)>,
+}
+
+impl Default for Token {
+    fn default() -> Token {
+        return Token::new();
+    }
 }
 
 impl Token {
     pub fn new() -> Token {
         return Token {
             token_type: TokenType::TokenReturn,
-            start: ['\0'; 256],
+            start: 0,
             length: 0,
-            line: 0,
+            line: 1,
+            column: 1,
+            message: "",
+            doc: None,
+        };
+    }
+
+    pub fn create(
+        token_type: TokenType,
+        start: usize,
+        length: usize,
+        line: i64,
+        column: i64,
+    ) -> Token {
+        return Token {
+            token_type,
+            start,
+            length,
+            line,
+            column,
+            message: "",
+            doc: None,
         };
     }
 
-    pub fn create(token_type: TokenType, start: [char; 256], length: usize, line: i64) -> Token {
+    pub fn create_error(
+        message: &'static str,
+        start: usize,
+        length: usize,
+        line: i64,
+        column: i64,
+    ) -> Token {
         return Token {
-            token_type: token_type,
-            start: start,
-            length: length,
-            line: line,
+            token_type: TokenType::TokenError,
+            start,
+            length,
+            line,
+            column,
+            message,
+            doc: None,
         };
     }
 
@@ -75,124 +118,247 @@ impl Token {
         return self.token_type;
     }
 
+    /// The 1-based line the token starts on.
     pub fn get_line(&self) -> i64 {
         return self.line;
     }
 
-    pub fn get_start(&self) -> [char; 256] {
+    /// The 1-based column, counted in characters, the token starts at.
+    pub fn get_column(&self) -> i64 {
+        return self.column;
+    }
+
+    /// The byte range the token covers in the scanned source.
+    pub fn get_range(&self) -> std::ops::Range<usize> {
+        return self.start..self.start + self.length;
+    }
+
+    pub fn get_start(&self) -> usize {
         return self.start;
     }
 
     pub fn get_length(&self) -> usize {
         return self.length;
     }
+
+    pub fn get_message(&self) -> &'static str {
+        return self.message;
+    }
+
+    /// The byte offset and length of the `///` comment lines directly
+    /// preceding this token, if it starts a declaration.
+    pub fn get_doc(&self) -> Option<(usize, usize)> {
+        return self.doc;
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Scanner {
-    start: [char; 256],
+#[derive(Clone, Debug)]
+pub struct Scanner<'a> {
+    source: std::borrow::Cow<'a, str>,
     tok_beg: usize,
     current: usize,
     line: i64,
+    column: i64,
+    tok_line: i64,
+    tok_column: i64,
+    interpolation: Vec<usize>,
+    doc: Option<(usize, usize)>,
+    finished: bool,
 }
 
-impl Scanner {
-    pub fn new() -> Scanner {
+impl<'a> Scanner<'a> {
+    /// A leading byte order mark and `#!` interpreter line are skipped, so
+    /// scripts can be run directly as executables.
+    pub fn create(source: impl Into<std::borrow::Cow<'a, str>>) -> Scanner<'a> {
+        let source: std::borrow::Cow<'a, str> = source.into();
+        let mut start: usize = 0;
+        if source.starts_with('\u{feff}') {
+            start = '\u{feff}'.len_utf8();
+        }
+        if source[start..].starts_with("#!") {
+            start += source[start..].find('\n').unwrap_or(source.len() - start);
+        }
+
         return Scanner {
-            start: ['\0'; 256],
-            tok_beg: 0,
-            current: 0,
-            line: 0,
+            source,
+            tok_beg: start,
+            current: start,
+            line: 1,
+            column: 1,
+            tok_line: 1,
+            tok_column: 1,
+            interpolation: Vec::new(),
+            doc: None,
+            finished: false,
         };
     }
 
-    pub fn create(source: String) -> Scanner {
-        let chars = source.chars().into_iter().collect::<Vec<char>>();
-        let len: usize = source.chars().into_iter().collect::<Vec<char>>().len();
+    pub fn lexeme(&self, token: &Token) -> &str {
+        return &self.source[token.start..token.start + token.length];
+    }
+
+    /// Returns the text of a token's doc comment with the `///` markers
+    /// removed, one line per comment line.
+    pub fn doc_comment(&self, token: &Token) -> Option<String> {
+        let (start, length) = token.get_doc()?;
+        let lines: Vec<&str> = self.source[start..start + length]
+            .lines()
+            .map(|line| line.trim_start().trim_start_matches("///"))
+            .map(|line| line.strip_prefix(' ').unwrap_or(line))
+            .collect();
+        return Some(lines.join("\n"));
+    }
 
-        let mut src: [char; 256] = ['\0'; 256];
+    /// Decodes the escape sequences of a string or interpolation segment,
+    /// which the scanner has already validated, and strips its delimiters.
+    pub fn string_value(&self, token: &Token) -> String {
+        let lexeme: &str = self.lexeme(token);
+        let closing: usize = if token.get_type() == TokenType::TokenInterpolation {
+            "${".len()
+        } else {
+            "\"".len()
+        };
+        let mut chars = lexeme[1..lexeme.len() - closing].chars();
+        let mut value: String = String::new();
 
-        for i in 0..len {
-            src[i] = chars[i];
+        while let Some(c) = chars.next() {
+            if c == '\r' && chars.clone().next() == Some('\n') {
+                continue;
+            }
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('u') => {
+                    chars.next();
+                    let digits: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    if let Some(decoded) = u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                    {
+                        value.push(decoded);
+                    }
+                }
+                Some(escaped) => value.push(escaped),
+                None => {}
+            }
         }
 
-        return Scanner {
-            start: src,
-            tok_beg: 0,
-            current: 0,
-            line: 0,
-        };
+        return value;
     }
 
-    fn create_error_token(&self) -> Token {
-        let chars = "Unexpected Character"
-            .chars()
-            .into_iter()
-            .collect::<Vec<char>>();
-        let len: usize = "Unexpected Character"
-            .chars()
-            .into_iter()
-            .collect::<Vec<char>>()
-            .len();
-        let mut src: [char; 256] = ['\0'; 256];
-
-        for i in 0..len {
-            src[i] = chars[i];
-        }
+    /// Converts a number token, which the scanner has already validated,
+    /// to its value. Returns None when an integer literal does not fit.
+    pub fn number_value(&self, token: &Token) -> Option<f64> {
+        let digits: String = self.lexeme(token).replace('_', "");
+        let radix: u32 = match digits.get(..2) {
+            Some("0x") | Some("0X") => 16,
+            Some("0b") | Some("0B") => 2,
+            Some("0o") | Some("0O") => 8,
+            _ => return digits.parse::<f64>().ok(),
+        };
+        return u64::from_str_radix(&digits[2..], radix)
+            .ok()
+            .map(|value| value as f64);
+    }
 
-        return Token::create(
-            TokenType::TokenError,
-            src,
-            len.try_into().unwrap(),
-            self.line,
+    fn create_error_token(&self, message: &'static str) -> Token {
+        return Token::create_error(
+            message,
+            self.tok_beg,
+            self.current - self.tok_beg,
+            self.tok_line,
+            self.tok_column,
         );
     }
 
     fn create_token_from_type(&self, token_type: TokenType) -> Token {
-        let mut data: [char; 256] = ['\0'; 256];
-        let tmp = &self.start[self.tok_beg..self.current];
-
-        for i in 0..tmp.len() {
-            data[i] = tmp[i];
-        }
-
-        return Token::create(token_type, data, self.current - self.tok_beg, self.line);
+        return Token::create(
+            token_type,
+            self.tok_beg,
+            self.current - self.tok_beg,
+            self.tok_line,
+            self.tok_column,
+        );
     }
 
     fn check_keyword(
         &self,
         start: i64,
         length: i64,
-        rest: String,
+        rest: &str,
         token_type: TokenType,
     ) -> TokenType {
-        if self.current - self.tok_beg == (start + length).try_into().unwrap() {
+        let start = self.tok_beg + start as usize;
+        let length = length as usize;
+        if self.current == start + length
+            && &self.source.as_bytes()[start..start + length] == rest.as_bytes()
+        {
             return token_type;
         }
         return TokenType::TokenIdentifier;
     }
 
     fn identifier_type(&self) -> TokenType {
-        match self.start[self.tok_beg] {
-            'a' => return self.check_keyword(1, 2, "nd".to_string(), TokenType::TokenAnd),
-            'c' => todo!(),
-            'e' => todo!(),
-            'f' => todo!(),
-            'i' => todo!(),
-            'n' => todo!(),
-            'o' => todo!(),
-            'p' => return self.check_keyword(1, 4, "rint".to_string(), TokenType::TokenPrint),
-            'r' => todo!(),
-            's' => todo!(),
-            't' => todo!(),
-            'v' => return self.check_keyword(1, 2, "ar".to_string(), TokenType::TokenVar),
-            'w' => todo!(),
+        match self.source.as_bytes()[self.tok_beg] as char {
+            'a' => return self.check_keyword(1, 2, "nd", TokenType::TokenAnd),
+            'b' => return self.check_keyword(1, 4, "reak", TokenType::TokenBreak),
+            'c' => {
+                if self.current - self.tok_beg > 1 {
+                    match self.source.as_bytes()[self.tok_beg + 1] as char {
+                        'l' => {
+                            return self.check_keyword(2, 3, "ass", TokenType::TokenClass)
+                        }
+                        'o' => {
+                            return self.check_keyword(2, 6, "ntinue", TokenType::TokenContinue)
+                        }
+                        _ => {}
+                    }
+                }
+                return TokenType::TokenIdentifier;
+            }
+            'e' => return self.check_keyword(1, 3, "lse", TokenType::TokenElse),
+            'f' => {
+                if self.current - self.tok_beg > 1 {
+                    match self.source.as_bytes()[self.tok_beg + 1] as char {
+                        'a' => {
+                            return self.check_keyword(2, 3, "lse", TokenType::TokenFalse)
+                        }
+                        'o' => return self.check_keyword(2, 1, "r", TokenType::TokenFor),
+                        'u' => return self.check_keyword(2, 1, "n", TokenType::TokenFun),
+                        _ => {}
+                    }
+                }
+                return TokenType::TokenIdentifier;
+            }
+            'i' => return self.check_keyword(1, 1, "f", TokenType::TokenIf),
+            'n' => return self.check_keyword(1, 2, "il", TokenType::TokenNil),
+            'o' => return self.check_keyword(1, 1, "r", TokenType::TokenOr),
+            'p' => return self.check_keyword(1, 4, "rint", TokenType::TokenPrint),
+            'r' => return self.check_keyword(1, 5, "eturn", TokenType::TokenReturn),
+            's' => return self.check_keyword(1, 4, "uper", TokenType::TokenSuper),
+            't' => {
+                if self.current - self.tok_beg > 1 {
+                    match self.source.as_bytes()[self.tok_beg + 1] as char {
+                        'h' => return self.check_keyword(2, 2, "is", TokenType::TokenThis),
+                        'r' => return self.check_keyword(2, 2, "ue", TokenType::TokenTrue),
+                        _ => {}
+                    }
+                }
+                return TokenType::TokenIdentifier;
+            }
+            'v' => return self.check_keyword(1, 2, "ar", TokenType::TokenVar),
+            'w' => return self.check_keyword(1, 4, "hile", TokenType::TokenWhile),
             _ => return TokenType::TokenIdentifier,
         }
     }
 
     fn identifier(&mut self) -> Token {
-        while self.is_alpha(self.start[self.current]) || self.is_digit(self.start[self.current]) {
+        while self.is_identifier_continue(self.peek()) {
             self.advance();
         }
 
@@ -200,72 +366,324 @@ impl Scanner {
     }
 
     fn number(&mut self) -> Token {
-        while self.is_digit(self.start[self.current]) {
+        let mut error: Option<&'static str> = None;
+
+        if self.source.as_bytes()[self.tok_beg] == b'0' {
+            let radix: Option<(u32, &'static str, &'static str)> = match self.peek() {
+                'x' | 'X' => Some((
+                    16,
+                    "Expect hexadecimal digits after '0x'.",
+                    "Invalid digit in hexadecimal literal.",
+                )),
+                'b' | 'B' => Some((
+                    2,
+                    "Expect binary digits after '0b'.",
+                    "Invalid digit in binary literal.",
+                )),
+                'o' | 'O' => Some((
+                    8,
+                    "Expect octal digits after '0o'.",
+                    "Invalid digit in octal literal.",
+                )),
+                _ => None,
+            };
+            if let Some((radix, missing, invalid)) = radix {
+                self.advance();
+                if self.digits(radix, &mut error) == 0 {
+                    error.get_or_insert(missing);
+                }
+                if self.peek().is_ascii_alphanumeric() {
+                    while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+                        self.advance();
+                    }
+                    error.get_or_insert(invalid);
+                }
+                return self.number_token(error);
+            }
+        }
+
+        self.digits(10, &mut error);
+        if self.peek() == '.' && self.is_digit(self.peek_next()) {
             self.advance();
+            self.digits(10, &mut error);
         }
-        if self.start[self.current] == '.' && self.is_digit(self.start[self.current + 1]) {
+        if self.peek() == 'e' || self.peek() == 'E' {
             self.advance();
-            while self.is_digit(self.start[self.current]) {
+            if self.peek() == '+' || self.peek() == '-' {
                 self.advance();
             }
+            if self.digits(10, &mut error) == 0 {
+                error.get_or_insert("Expect digits in exponent.");
+            }
+        }
+        return self.number_token(error);
+    }
+
+    /// Consumes a run of digits in `radix`, allowing single `_` separators
+    /// between them, and returns how many digits were read.
+    fn digits(&mut self, radix: u32, error: &mut Option<&'static str>) -> usize {
+        let mut count: usize = 0;
+        let mut after_separator: bool = self.source.as_bytes()[self.current - 1] == b'_';
+
+        loop {
+            let c: char = self.peek();
+            if c == '_' {
+                if after_separator {
+                    error.get_or_insert("Repeated '_' in number literal.");
+                } else if count == 0 && !self.source.as_bytes()[self.current - 1].is_ascii_digit() {
+                    error.get_or_insert("'_' must come after a digit.");
+                }
+                after_separator = true;
+            } else if c.is_digit(radix) {
+                after_separator = false;
+                count += 1;
+            } else {
+                break;
+            }
+            self.advance();
+        }
+
+        if after_separator {
+            error.get_or_insert("Number literal cannot end with '_'.");
+        }
+        return count;
+    }
+
+    fn number_token(&self, error: Option<&'static str>) -> Token {
+        match error {
+            Some(message) => return self.create_error_token(message),
+            None => return self.create_token_from_type(TokenType::TokenNumber),
+        }
+    }
+
+    /// Scans the rest of a string literal. A `${` ends the current segment
+    /// with a TokenInterpolation; scanning resumes here at its matching `}`.
+    fn string(&mut self) -> Token {
+        let mut error: Option<&'static str> = None;
+        while self.peek() != '"' && !self.is_at_end() {
+            if self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolation.push(0);
+                match error {
+                    Some(message) => return self.create_error_token(message),
+                    None => return self.create_token_from_type(TokenType::TokenInterpolation),
+                }
+            }
+            if self.advance() == '\\' {
+                if let Err(message) = self.escape() {
+                    error.get_or_insert(message);
+                }
+            }
+        }
+
+        if self.is_at_end() {
+            return self.create_error_token("Unterminated string.");
+        }
+
+        self.advance();
+        match error {
+            Some(message) => return self.create_error_token(message),
+            None => return self.create_token_from_type(TokenType::TokenString),
+        }
+    }
+
+    fn escape(&mut self) -> Result<(), &'static str> {
+        match self.peek() {
+            'n' | 't' | 'r' | '"' | '\\' | '$' => {
+                self.advance();
+                return Ok(());
+            }
+            'u' => {
+                self.advance();
+                if !self.match_to('{') {
+                    return Err("Expect '{' after '\\u'.");
+                }
+                let digits_start: usize = self.current;
+                while self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+                let digits_end: usize = self.current;
+                if !self.match_to('}') {
+                    return Err("Unterminated unicode escape sequence.");
+                }
+                let digits: &str = &self.source[digits_start..digits_end];
+                if digits.is_empty()
+                    || digits.len() > 6
+                    || char::from_u32(u32::from_str_radix(digits, 16).unwrap_or(u32::MAX)).is_none()
+                {
+                    return Err("Invalid unicode escape sequence.");
+                }
+                return Ok(());
+            }
+            _ => return Err("Invalid escape sequence."),
         }
-        return self.create_token_from_type(TokenType::TokenNumber);
     }
 
     fn is_at_end(&self) -> bool {
-        return self.start[self.current + 1] == '\0';
+        return self.current >= self.source.len();
+    }
+
+    fn peek(&self) -> char {
+        return self.source[self.current..].chars().next().unwrap_or('\0');
+    }
+
+    fn peek_next(&self) -> char {
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        return chars.next().unwrap_or('\0');
     }
-    fn is_alpha(&self, c: char) -> bool {
-        return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '-';
+    fn is_identifier_start(&self, c: char) -> bool {
+        return c == '_' || unicode::is_xid_start(c);
+    }
+    fn is_identifier_continue(&self, c: char) -> bool {
+        return unicode::is_xid_continue(c);
     }
     fn is_digit(&self, c: char) -> bool {
-        return c >= '0' && c <= '9';
+        return c.is_ascii_digit();
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        return self.start[self.current - 1];
+        let c: char = self.peek();
+        self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        return c;
     }
 
-    fn skip_whitespace(&mut self) {
+    fn begin_token(&mut self) {
+        self.tok_beg = self.current;
+        self.tok_line = self.line;
+        self.tok_column = self.column;
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), &'static str> {
         loop {
-            let c: char = self.start[self.current];
+            let c: char = self.peek();
             match c {
-                ' ' | '\r' | '\t' | '\0' => {
+                ' ' | '\r' | '\t' => {
                     self.advance();
-                    break;
                 }
                 '\n' => {
-                    self.line += 1;
                     self.advance();
-                    return;
                 }
-                '/' => todo!(),
-                _ => return,
+                '/' => {
+                    if self.peek_next() == '/' {
+                        self.line_comment();
+                    } else if self.peek_next() == '*' {
+                        self.block_comment()?;
+                    } else {
+                        return Ok(());
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn line_comment(&mut self) {
+        let comment_start: usize = self.current;
+        while self.peek() != '\n' && !self.is_at_end() {
+            self.advance();
+        }
+
+        let comment: &str = &self.source[comment_start..self.current];
+        if comment.starts_with("///") && !comment.starts_with("////") {
+            let doc_start: usize = match self.doc {
+                Some((start, _)) => start,
+                None => comment_start,
+            };
+            self.doc = Some((doc_start, self.current - doc_start));
+        } else {
+            self.doc = None;
+        }
+    }
+
+    fn block_comment(&mut self) -> Result<(), &'static str> {
+        self.doc = None;
+        self.begin_token();
+        self.advance();
+        self.advance();
+
+        let mut depth: usize = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err("Unterminated block comment.");
+            }
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+            } else {
+                self.advance();
             }
         }
+        return Ok(());
     }
 
     fn match_to(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.start[self.current] != expected {
+        if self.peek() != expected {
             return false;
         }
-        self.current += 1;
+        self.advance();
         return true;
     }
 
+    /// Reports whether the next token starts with `expected`. Whitespace and
+    /// comments are skipped to find it, and the scanner is left where it was.
+    pub fn next_starts_with(&mut self, expected: char) -> bool {
+        let current: usize = self.current;
+        let line: i64 = self.line;
+        let column: i64 = self.column;
+        let tok_beg: usize = self.tok_beg;
+        let tok_line: i64 = self.tok_line;
+        let tok_column: i64 = self.tok_column;
+        let doc: Option<(usize, usize)> = self.doc;
+
+        let found: bool = self.skip_whitespace().is_ok() && self.peek() == expected;
+
+        self.current = current;
+        self.line = line;
+        self.column = column;
+        self.tok_beg = tok_beg;
+        self.tok_line = tok_line;
+        self.tok_column = tok_column;
+        self.doc = doc;
+        return found;
+    }
+
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
-        self.tok_beg = self.current;
+        let mut token: Token = self.scan_raw_token();
+        let doc: Option<(usize, usize)> = self.doc.take();
+        match token.get_type() {
+            TokenType::TokenClass | TokenType::TokenFun | TokenType::TokenVar => token.doc = doc,
+            _ => {}
+        }
+        return token;
+    }
+
+    fn scan_raw_token(&mut self) -> Token {
+        if let Err(message) = self.skip_whitespace() {
+            return self.create_error_token(message);
+        }
+        self.begin_token();
 
         if self.is_at_end() {
             return self.create_token_from_type(TokenType::TokenEof);
         }
         let c: char = self.advance();
-        if self.is_alpha(c) {
+        if self.is_identifier_start(c) {
             return self.identifier();
         }
 
@@ -274,18 +692,40 @@ impl Scanner {
         }
 
         match c {
-            '(' => todo!(),
-            ')' => todo!(),
-            '{' => todo!(),
-            '}' => todo!(),
+            '(' => return self.create_token_from_type(TokenType::TokenLeftParen),
+            ')' => return self.create_token_from_type(TokenType::TokenRightParen),
+            '{' => {
+                if let Some(depth) = self.interpolation.last_mut() {
+                    *depth += 1;
+                }
+                return self.create_token_from_type(TokenType::TokenLeftBrace);
+            }
+            '}' => {
+                match self.interpolation.last_mut() {
+                    Some(0) => {
+                        self.interpolation.pop();
+                        return self.string();
+                    }
+                    Some(depth) => *depth -= 1,
+                    None => {}
+                }
+                return self.create_token_from_type(TokenType::TokenRightBrace);
+            }
             ';' => return self.create_token_from_type(TokenType::TokenSemicolon),
-            ',' => todo!(),
-            '.' => todo!(),
-            '-' => todo!(),
+            ':' => return self.create_token_from_type(TokenType::TokenColon),
+            ',' => return self.create_token_from_type(TokenType::TokenComma),
+            '.' => return self.create_token_from_type(TokenType::TokenDot),
+            '-' => return self.create_token_from_type(TokenType::TokenMinus),
             '+' => return self.create_token_from_type(TokenType::TokenPlus),
-            '/' => todo!(),
-            '*' => todo!(),
-            '!' => todo!(),
+            '/' => return self.create_token_from_type(TokenType::TokenSlash),
+            '*' => return self.create_token_from_type(TokenType::TokenStar),
+            '!' => {
+                if self.match_to('=') {
+                    return self.create_token_from_type(TokenType::TokenBangEqual);
+                } else {
+                    return self.create_token_from_type(TokenType::TokenBang);
+                }
+            }
             '=' => {
                 if self.match_to('=') {
                     return self.create_token_from_type(TokenType::TokenEqualEqual);
@@ -293,24 +733,397 @@ impl Scanner {
                     return self.create_token_from_type(TokenType::TokenEqual);
                 }
             }
-            '<' => todo!(),
-            '>' => todo!(),
-            '"' => todo!(),
+            '<' => {
+                if self.match_to('=') {
+                    return self.create_token_from_type(TokenType::TokenLessEqual);
+                } else {
+                    return self.create_token_from_type(TokenType::TokenLess);
+                }
+            }
+            '>' => {
+                if self.match_to('=') {
+                    return self.create_token_from_type(TokenType::TokenGreaterEqual);
+                } else {
+                    return self.create_token_from_type(TokenType::TokenGreater);
+                }
+            }
+            '"' => return self.string(),
             _ => {
-                return self.create_error_token();
+                return self.create_error_token("Unexpected character.");
             }
         }
     }
+}
 
-    pub fn get_start(&self) -> [char; 256] {
-        return self.start;
+/// Yields every token in the source, ending with a single TokenEof.
+impl Iterator for Scanner<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+        let token: Token = self.scan_token();
+        if token.get_type() == TokenType::TokenEof {
+            self.finished = true;
+        }
+        return Some(token);
     }
+}
 
-    pub fn get_line(&self) -> i64 {
-        return self.line;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        let mut scanner: Scanner = Scanner::create(source);
+        let mut tokens: Vec<Token> = Vec::new();
+        loop {
+            let token: Token = scanner.scan_token();
+            tokens.push(token);
+            if token.get_type() == TokenType::TokenEof {
+                return tokens;
+            }
+        }
+    }
+
+    fn types(source: &str) -> Vec<TokenType> {
+        return tokens(source).iter().map(|token| token.get_type()).collect();
+    }
+
+    /// The first error token's message, or "" if the source scans cleanly.
+    fn error(source: &str) -> &'static str {
+        return tokens(source)
+            .iter()
+            .find(|token| token.get_type() == TokenType::TokenError)
+            .map_or("", |token| token.get_message());
     }
 
-    pub fn get_current(&self) -> usize {
-        return self.current;
+    #[test]
+    fn scans_every_keyword() {
+        assert_eq!(
+            types("and break class continue else false for fun if nil or print return super this true var while"),
+            vec![
+                TokenType::TokenAnd,
+                TokenType::TokenBreak,
+                TokenType::TokenClass,
+                TokenType::TokenContinue,
+                TokenType::TokenElse,
+                TokenType::TokenFalse,
+                TokenType::TokenFor,
+                TokenType::TokenFun,
+                TokenType::TokenIf,
+                TokenType::TokenNil,
+                TokenType::TokenOr,
+                TokenType::TokenPrint,
+                TokenType::TokenReturn,
+                TokenType::TokenSuper,
+                TokenType::TokenThis,
+                TokenType::TokenTrue,
+                TokenType::TokenVar,
+                TokenType::TokenWhile,
+                TokenType::TokenEof,
+            ]
+        );
+    }
+
+    #[test]
+    fn keyword_prefixes_and_extensions_are_identifiers() {
+        for source in ["an", "ands", "classy", "c", "cont", "f", "fo", "fund", "t", "thisx", "whiles"] {
+            assert_eq!(
+                types(source),
+                vec![TokenType::TokenIdentifier, TokenType::TokenEof],
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn scans_every_punctuator() {
+        assert_eq!(
+            types("(){},.-+;:/ * ! != = == > >= < <="),
+            vec![
+                TokenType::TokenLeftParen,
+                TokenType::TokenRightParen,
+                TokenType::TokenLeftBrace,
+                TokenType::TokenRightBrace,
+                TokenType::TokenComma,
+                TokenType::TokenDot,
+                TokenType::TokenMinus,
+                TokenType::TokenPlus,
+                TokenType::TokenSemicolon,
+                TokenType::TokenColon,
+                TokenType::TokenSlash,
+                TokenType::TokenStar,
+                TokenType::TokenBang,
+                TokenType::TokenBangEqual,
+                TokenType::TokenEqual,
+                TokenType::TokenEqualEqual,
+                TokenType::TokenGreater,
+                TokenType::TokenGreaterEqual,
+                TokenType::TokenLess,
+                TokenType::TokenLessEqual,
+                TokenType::TokenEof,
+            ]
+        );
+        assert_eq!(error("@"), "Unexpected character.");
+    }
+
+    #[test]
+    fn scans_sources_and_tokens_longer_than_256_bytes() {
+        let name: String = "n".repeat(1000);
+        let source: String = format!("{}\"{}\"{}", " ".repeat(300), "s".repeat(70000), name);
+        let ranges: Vec<(usize, usize)> = tokens(&source)
+            .iter()
+            .map(|token| (token.get_start(), token.get_start() + token.get_length()))
+            .collect();
+        assert_eq!(ranges, vec![(300, 70302), (70302, 71302), (71302, 71302)]);
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        let source: &str = r#""a\nb\tc\rd\"e\\f\$g\u{48}\u{1F600}""#;
+        let mut scanner: Scanner = Scanner::create(source);
+        let token: Token = scanner.scan_token();
+        assert_eq!(token.get_type(), TokenType::TokenString);
+        assert_eq!(scanner.string_value(&token), "a\nb\tc\rd\"e\\f$gH\u{1F600}");
+    }
+
+    #[test]
+    fn strings_can_span_lines() {
+        let mut scanner: Scanner = Scanner::create("\"one\ntwo\" x");
+        let string: Token = scanner.scan_token();
+        assert_eq!(scanner.string_value(&string), "one\ntwo");
+        assert_eq!(scanner.scan_token().get_line(), 2);
+    }
+
+    #[test]
+    fn reports_bad_string_escapes() {
+        assert_eq!(error(r#""\q""#), "Invalid escape sequence.");
+        assert_eq!(error(r#""\u41""#), "Expect '{' after '\\u'.");
+        assert_eq!(error(r#""\u{41""#), "Unterminated unicode escape sequence.");
+        assert_eq!(error(r#""\u{}""#), "Invalid unicode escape sequence.");
+        assert_eq!(error(r#""\u{1234567}""#), "Invalid unicode escape sequence.");
+        assert_eq!(error(r#""\u{110000}""#), "Invalid unicode escape sequence.");
+        assert_eq!(error(r#""\u{D800}""#), "Invalid unicode escape sequence.");
+        assert_eq!(error("\"open"), "Unterminated string.");
+    }
+
+    #[test]
+    fn scans_nested_interpolation() {
+        let source: &str = r#""a${ "b${c}d" }e${ f({}) }""#;
+        let mut scanner: Scanner = Scanner::create(source);
+        let mut segments: Vec<(TokenType, String)> = Vec::new();
+        loop {
+            let token: Token = scanner.scan_token();
+            let text: String = match token.get_type() {
+                TokenType::TokenString | TokenType::TokenInterpolation => scanner.string_value(&token),
+                _ => scanner.lexeme(&token).to_string(),
+            };
+            segments.push((token.get_type(), text));
+            if token.get_type() == TokenType::TokenEof {
+                break;
+            }
+        }
+
+        let expected: Vec<(TokenType, &str)> = vec![
+            (TokenType::TokenInterpolation, "a"),
+            (TokenType::TokenInterpolation, "b"),
+            (TokenType::TokenIdentifier, "c"),
+            (TokenType::TokenString, "d"),
+            (TokenType::TokenInterpolation, "e"),
+            (TokenType::TokenIdentifier, "f"),
+            (TokenType::TokenLeftParen, "("),
+            (TokenType::TokenLeftBrace, "{"),
+            (TokenType::TokenRightBrace, "}"),
+            (TokenType::TokenRightParen, ")"),
+            (TokenType::TokenString, ""),
+            (TokenType::TokenEof, ""),
+        ];
+        let expected: Vec<(TokenType, String)> = expected
+            .into_iter()
+            .map(|(token_type, text)| (token_type, text.to_string()))
+            .collect();
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn reports_unterminated_interpolation() {
+        assert_eq!(error(r#""a${b}c"#), "Unterminated string.");
+    }
+
+    #[test]
+    fn skips_line_and_nested_block_comments() {
+        assert_eq!(
+            types("a // line\nb /* outer /* inner */ still outer */ c /**/"),
+            vec![
+                TokenType::TokenIdentifier,
+                TokenType::TokenIdentifier,
+                TokenType::TokenIdentifier,
+                TokenType::TokenEof,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_block_comment() {
+        let token: Token = tokens("a\n  /* /* */")[1];
+        assert_eq!(token.get_type(), TokenType::TokenError);
+        assert_eq!(token.get_message(), "Unterminated block comment.");
+        assert_eq!((token.get_line(), token.get_column()), (2, 3));
+    }
+
+    #[test]
+    fn attaches_doc_comments_to_declarations() {
+        let source: &str = "/// Adds one.\n///   Indented.\nfun f() {}\n//// Not a doc.\nvar a;\n/// Lost.\nprint 1;\nclass C {}";
+        let mut scanner: Scanner = Scanner::create(source);
+        let mut docs: Vec<(TokenType, Option<String>)> = Vec::new();
+        loop {
+            let token: Token = scanner.scan_token();
+            match token.get_type() {
+                TokenType::TokenEof => break,
+                TokenType::TokenClass | TokenType::TokenFun | TokenType::TokenVar => {
+                    docs.push((token.get_type(), scanner.doc_comment(&token)));
+                }
+                _ => assert_eq!(token.get_doc(), None),
+            }
+        }
+
+        assert_eq!(
+            docs,
+            vec![
+                (TokenType::TokenFun, Some("Adds one.\n  Indented.".to_string())),
+                (TokenType::TokenVar, None),
+                (TokenType::TokenClass, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn looks_ahead_without_consuming() {
+        let mut scanner: Scanner = Scanner::create("a /* b */\n  : c");
+        assert_eq!(scanner.scan_token().get_type(), TokenType::TokenIdentifier);
+        assert!(scanner.next_starts_with(':'));
+        assert!(!scanner.next_starts_with('c'));
+
+        let colon: Token = scanner.scan_token();
+        assert_eq!(colon.get_type(), TokenType::TokenColon);
+        assert_eq!((colon.get_line(), colon.get_column(), colon.get_range()), (2, 3, 12..13));
+    }
+
+    #[test]
+    fn other_comments_end_a_doc_comment() {
+        let source: &str = "/// a\n// plain\n/// c\nfun f() {}\n/// d\n/* block */\nvar x;";
+        let tokens: Vec<Token> = tokens(source);
+        let scanner: Scanner = Scanner::create(source);
+        assert_eq!(tokens[0].get_type(), TokenType::TokenFun);
+        assert_eq!(scanner.doc_comment(&tokens[0]), Some("c".to_string()));
+        assert_eq!(tokens[6].get_type(), TokenType::TokenVar);
+        assert_eq!(tokens[6].get_doc(), None);
+    }
+
+    #[test]
+    fn tracks_line_column_and_byte_range() {
+        let spans: Vec<(i64, i64, std::ops::Range<usize>)> = tokens("var é = \"ü\";\n\tx \"a\nbc\" d")
+            .iter()
+            .map(|token| (token.get_line(), token.get_column(), token.get_range()))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (1, 1, 0..3),
+                (1, 5, 4..6),
+                (1, 7, 7..8),
+                (1, 9, 9..13),
+                (1, 12, 13..14),
+                (2, 2, 16..17),
+                (2, 4, 18..24),
+                (3, 5, 25..26),
+                (3, 6, 26..26),
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_number_literals() {
+        let cases: [(&str, Option<f64>); 9] = [
+            ("42", Some(42.0)),
+            ("1_000", Some(1000.0)),
+            ("0xFF", Some(255.0)),
+            ("0x1_f", Some(31.0)),
+            ("0b1010", Some(10.0)),
+            ("0o17", Some(15.0)),
+            ("1.5e3", Some(1500.0)),
+            ("25E-2", Some(0.25)),
+            ("0x1_0000_0000_0000_0000", None),
+        ];
+        for (source, value) in cases {
+            let mut scanner: Scanner = Scanner::create(source);
+            let token: Token = scanner.scan_token();
+            assert_eq!(token.get_type(), TokenType::TokenNumber, "{:?}", source);
+            assert_eq!(scanner.number_value(&token), value, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn reports_malformed_number_literals() {
+        let cases: [(&str, &str); 11] = [
+            ("0x", "Expect hexadecimal digits after '0x'."),
+            ("0b", "Expect binary digits after '0b'."),
+            ("0o", "Expect octal digits after '0o'."),
+            ("0x1g", "Invalid digit in hexadecimal literal."),
+            ("0b102", "Invalid digit in binary literal."),
+            ("0o78", "Invalid digit in octal literal."),
+            ("1__0", "Repeated '_' in number literal."),
+            ("1_", "Number literal cannot end with '_'."),
+            ("1.5_", "Number literal cannot end with '_'."),
+            ("0x_1", "'_' must come after a digit."),
+            ("1e+", "Expect digits in exponent."),
+        ];
+        for (source, message) in cases {
+            // The whole literal becomes one error token.
+            assert_eq!(types(source), vec![TokenType::TokenError, TokenType::TokenEof], "{:?}", source);
+            assert_eq!(error(source), message, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn scans_unicode_and_underscore_identifiers() {
+        let source: &str = "_ __init a_1 café π 変数 e\u{301} x\u{663} varé";
+        let mut scanner: Scanner = Scanner::create(source);
+        let mut lexemes: Vec<String> = Vec::new();
+        loop {
+            let token: Token = scanner.scan_token();
+            if token.get_type() == TokenType::TokenEof {
+                break;
+            }
+            assert_eq!(token.get_type(), TokenType::TokenIdentifier, "{:?}", scanner.lexeme(&token));
+            lexemes.push(scanner.lexeme(&token).to_string());
+        }
+        assert_eq!(
+            lexemes,
+            vec!["_", "__init", "a_1", "café", "π", "変数", "e\u{301}", "x\u{663}", "varé"]
+        );
+    }
+
+    #[test]
+    fn dash_is_not_an_identifier_character() {
+        assert_eq!(
+            types("kebab-case"),
+            vec![
+                TokenType::TokenIdentifier,
+                TokenType::TokenMinus,
+                TokenType::TokenIdentifier,
+                TokenType::TokenEof,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_characters_that_cannot_start_an_identifier() {
+        for source in ["\u{663}x", "\u{301}", "€"] {
+            assert_eq!(error(source), "Unexpected character.", "{:?}", source);
+        }
     }
 }
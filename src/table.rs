@@ -1,62 +1,178 @@
 use crate::*;
 
-#[derive(Copy, Clone)]
+use std::rc::Rc;
+
+const TABLE_MAX_LOAD: f64 = 0.75;
+
+#[derive(Clone)]
 struct Entry {
-    key: object::ObjString,
+    key: Option<Rc<object::ObjString>>,
     value: value::Value,
 }
 
 impl Entry {
     fn new() -> Entry {
         return Entry {
-            key: object::ObjString::new(),
+            key: None,
             value: value::Value::new(),
         };
     }
+
+    fn is_tombstone(&self) -> bool {
+        return self.key.is_none() && self.value.get_value_type() != value::ValueType::ValNil;
+    }
 }
 
 pub struct Table {
-    count: i64,
-    capacity: i64,
-    entries: [Entry; 256],
+    count: usize,
+    entries: Vec<Entry>,
+}
+
+impl Default for Table {
+    fn default() -> Table {
+        return Table::new();
+    }
 }
 
 impl Table {
     pub fn new() -> Table {
         return Table {
             count: 0,
-            capacity: 0,
-            entries: [Entry::new(); 256],
+            entries: Vec::new(),
         };
     }
 
-    pub fn table_get(&self, obj: object::ObjString, value: value::Value) -> bool {
-        todo!();
-        }
+    fn find_entry(entries: &[Entry], key: &Rc<object::ObjString>) -> usize {
+        let capacity: usize = entries.len();
+        let mut index: usize = key.get_hash() as usize % capacity;
+        let mut tombstone: Option<usize> = None;
 
-    pub fn table_set(&self, obj: object::ObjString, value: value::Value) -> bool {
-        todo!();
+        loop {
+            let entry: &Entry = &entries[index];
+            match &entry.key {
+                None => {
+                    if !entry.is_tombstone() {
+                        return tombstone.unwrap_or(index);
+                    }
+                    if tombstone.is_none() {
+                        tombstone = Some(index);
+                    }
+                }
+                Some(existing) => {
+                    if Rc::ptr_eq(existing, key) {
+                        return index;
+                    }
+                }
+            }
+            index = (index + 1) % capacity;
+        }
     }
 
-    pub fn table_delete(&self, obj: object::ObjString) -> bool {
-        todo!();
+    fn adjust_capacity(&mut self, capacity: usize) {
+        let mut entries: Vec<Entry> = vec![Entry::new(); capacity];
+
+        self.count = 0;
+        for entry in self.entries.iter() {
+            if let Some(key) = &entry.key {
+                let index: usize = Table::find_entry(&entries, key);
+                entries[index] = entry.clone();
+                self.count += 1;
+            }
+        }
+
+        self.entries = entries;
     }
 
-    pub fn table_find_string(&self, chars: [char; 256], length: usize, hash: u32) -> Option<object::ObjString> {
+    pub fn table_get(&self, key: &Rc<object::ObjString>) -> Option<value::Value> {
         if self.count == 0 {
             return None;
         }
 
-        let index: u32 = hash % (self.capacity as u32);
+        let entry: &Entry = &self.entries[Table::find_entry(&self.entries, key)];
+        return entry.key.as_ref().map(|_| entry.value.clone());
+    }
+
+    /// Returns true when `key` was not already present.
+    pub fn table_set(&mut self, key: Rc<object::ObjString>, value: value::Value) -> bool {
+        if (self.count + 1) as f64 > self.entries.len() as f64 * TABLE_MAX_LOAD {
+            let capacity: usize = if self.entries.len() < 8 {
+                8
+            } else {
+                self.entries.len() * 2
+            };
+            self.adjust_capacity(capacity);
+        }
+
+        let index: usize = Table::find_entry(&self.entries, &key);
+        let entry: &mut Entry = &mut self.entries[index];
+        let is_new_key: bool = entry.key.is_none();
+        if is_new_key && !entry.is_tombstone() {
+            self.count += 1;
+        }
 
-        loop{
-            let entry: Entry = self.entries[index as usize];
-            if entry.key.get_length() == 0 {
-                return None;
+        entry.key = Some(key);
+        entry.value = value;
+        return is_new_key;
+    }
+
+    /// Copies every entry of this table into `to`.
+    pub fn table_add_all(&self, to: &mut Table) {
+        for entry in self.entries.iter() {
+            if let Some(key) = &entry.key {
+                to.table_set(Rc::clone(key), entry.value.clone());
             }
         }
-        todo!();
     }
 
-}
+    /// Empties the table, handing back the values it held.
+    pub fn table_take_values(&mut self) -> Vec<value::Value> {
+        self.count = 0;
+        return std::mem::take(&mut self.entries)
+            .into_iter()
+            .filter(|entry| entry.key.is_some())
+            .map(|entry| entry.value)
+            .collect();
+    }
+
+    pub fn table_delete(&mut self, key: &Rc<object::ObjString>) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let index: usize = Table::find_entry(&self.entries, key);
+        let entry: &mut Entry = &mut self.entries[index];
+        if entry.key.is_none() {
+            return false;
+        }
+
+        entry.key = None;
+        entry.value = value::Value::create(value::ValueType::ValBool, value::Union::create_bool(true));
+        return true;
+    }
 
+    pub fn table_find_string(&self, chars: &str, hash: u32) -> Option<Rc<object::ObjString>> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let capacity: usize = self.entries.len();
+        let mut index: usize = hash as usize % capacity;
+
+        loop {
+            let entry: &Entry = &self.entries[index];
+            match &entry.key {
+                None => {
+                    if !entry.is_tombstone() {
+                        return None;
+                    }
+                }
+                Some(key) => {
+                    if key.get_hash() == hash && key.get_chars() == chars {
+                        return Some(Rc::clone(key));
+                    }
+                }
+            }
+            index = (index + 1) % capacity;
+        }
+    }
+}
@@ -10,58 +10,68 @@ macro_rules! ternary {
     };
 }
 
-#[derive(Copy, Clone)]
-pub union Union {
-    boolean: bool,
-    number: i64,
-    obj: object::ObjString,
+#[derive(Clone, PartialEq)]
+pub enum Union {
+    Boolean(bool),
+    Number(f64),
+    Obj(object::Obj),
+}
+
+impl Default for Union {
+    fn default() -> Union {
+        return Union::new();
+    }
 }
 
 impl Union {
     pub fn new() -> Union {
-        return Union { boolean: false };
+        return Union::Boolean(false);
     }
 
     pub fn create_bool(value: bool) -> Union {
-        return Union { boolean: value };
+        return Union::Boolean(value);
     }
 
-    pub fn create_num(value: i64) -> Union {
-        return Union { number: value };
+    pub fn create_num(value: f64) -> Union {
+        return Union::Number(value);
     }
 
-    pub fn create_obj(value:object::ObjString) -> Union {
-        return Union { obj: value };
+    pub fn create_obj(value: object::Obj) -> Union {
+        return Union::Obj(value);
     }
 
     pub fn get_boolean(&self) -> bool {
-        unsafe {
-            return self.boolean;
+        match self {
+            Union::Boolean(value) => return *value,
+            _ => return false,
         }
     }
 
-    pub fn get_number(&self) -> i64 {
-        unsafe {
-            return self.number;
+    pub fn get_number(&self) -> f64 {
+        match self {
+            Union::Number(value) => return *value,
+            _ => return 0.0,
         }
     }
 
-    pub fn get_obj(&mut self) -> object::ObjString {
-        unsafe {
-            return self.obj;
+    pub fn get_obj(&self) -> object::Obj {
+        match self {
+            Union::Obj(value) => return value.clone(),
+            _ => panic!("value is not an object"),
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Default, PartialEq)]
 pub enum ValueType {
     ValBool,
+    #[default]
     ValNil,
     ValNumber,
     ValObj,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone, Default)]
 pub struct Value {
     value_type: ValueType,
     value: Union,
@@ -77,80 +87,94 @@ impl Value {
 
     pub fn create(value_type: ValueType, union: Union) -> Value {
         return Value {
-            value_type: value_type,
+            value_type,
             value: union,
         };
     }
 
-    pub fn get_value(&self) -> Union {
-        return self.value;
+    pub fn get_value(&self) -> &Union {
+        return &self.value;
     }
 
     pub fn get_value_type(&self) -> ValueType {
         return self.value_type;
     }
 
-    pub fn equal(&self, other: Value) -> bool {
+    /// The object handle this value holds, moved out rather than cloned.
+    pub fn into_obj(self) -> Option<object::Obj> {
+        match self.value {
+            Union::Obj(obj) => return Some(obj),
+            _ => return None,
+        }
+    }
+
+    /// Lox treats `nil` and `false` as false and every other value as true.
+    pub fn is_falsey(&self) -> bool {
+        match self.value_type {
+            ValueType::ValNil => return true,
+            ValueType::ValBool => return !self.value.get_boolean(),
+            _ => return false,
+        }
+    }
+
+    pub fn equal(&self, other: &Value) -> bool {
         if self.value_type != other.value_type {
             return false;
         }
 
         match self.value_type {
-            ValueType::ValBool => unsafe { return self.value.boolean == other.value.boolean },
             ValueType::ValNil => return true,
-            ValueType::ValNumber => unsafe { return self.value.number == other.value.number },
-            ValueType::ValObj => unsafe { return self.value.obj == other.value.obj },
-            _ => return false,
+            _ => return self.value == other.value,
         }
     }
 
     pub fn print(&self) -> String {
         match &self.value_type {
-            ValueType::ValBool => unsafe {
-                return ternary!(self.value.boolean => "true".to_string(); "false".to_string());
-            },
-            ValueType::ValNumber => unsafe {
-                return self.value.number.to_string();
-            },
-            ValueType::ValObj => unsafe {
-                return self.value.obj.get_chars().into_iter().collect::<String>();
-            },
+            ValueType::ValBool => {
+                return ternary!(self.value.get_boolean() => "true".to_string(); "false".to_string());
+            }
+            ValueType::ValNumber => {
+                return self.value.get_number().to_string();
+            }
+            ValueType::ValObj => {
+                return self.value.get_obj().print();
+            }
             ValueType::ValNil => {
                 return "nil".to_string();
             }
         }
     }
 
-    pub fn write_value_array(&self, mut array: ValueArray) {
-        array.values[array.count] = *self;
+    pub fn write_value_array(&self, array: &mut ValueArray) {
+        array.values.push(self.clone());
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct ValueArray {
-    capacity: usize,
-    count: usize,
-    values: [Value; 256],
+    values: Vec<Value>,
+}
+
+impl Default for ValueArray {
+    fn default() -> ValueArray {
+        return ValueArray::new();
+    }
 }
 
 impl ValueArray {
     pub fn new() -> ValueArray {
-        return ValueArray {
-            capacity: 0,
-            count: 0,
-            values: [Value::new(); 256],
-        };
+        return ValueArray { values: Vec::new() };
     }
 
     pub fn get_value(&self, i: usize) -> Value {
-        return self.values[i];
+        return self.values[i].clone();
     }
 
-    pub fn get_values(&self) -> [Value; 256] {
-        return self.values;
+    pub fn get_values(&self) -> &[Value] {
+        return &self.values;
     }
 
     pub fn get_count(&self) -> usize {
-        return self.count;
+        return self.values.len();
     }
 }
@@ -1,36 +1,400 @@
 use crate::*;
 
+macro_rules! binary_op {
+    ($vm:expr, $value_type:expr, $create:path, $op:tt) => {{
+        if $vm.peek(0).get_value_type() != value::ValueType::ValNumber
+            || $vm.peek(1).get_value_type() != value::ValueType::ValNumber
+        {
+            $vm.runtime_error("Operands must be numbers.".to_string());
+            return InterpretResult::InterpretRuntimeError;
+        }
+        let b: f64 = $vm.pop().get_value().get_number();
+        let a: f64 = $vm.pop().get_value().get_number();
+        $vm.push(value::Value::create($value_type, $create(a $op b)));
+    }};
+}
+
+/// The maximum call depth before a script is stopped with a stack overflow.
+const FRAMES_MAX: usize = 64;
+
+/// An error raised by native code, reported like any other runtime error.
+#[derive(Debug)]
+pub struct RuntimeError {
+    message: String,
+}
+
+impl RuntimeError {
+    pub fn create(message: String) -> RuntimeError {
+        return RuntimeError { message };
+    }
+
+    pub fn get_message(&self) -> &str {
+        return &self.message;
+    }
+}
+
 pub enum InterpretResult {
     InterpretOk,
     InterpretCompileError,
     InterpretRuntimeError,
 }
 
+/// An ongoing function call: the closure being run, its instruction
+/// pointer, and where its stack window begins.
+struct CallFrame {
+    closure: std::rc::Rc<object::ObjClosure>,
+    ip: usize,
+    slots: usize,
+}
+
 pub struct VM {
-    chunk: chunk::Chunk,
-    ip: Box<u8>,
+    frames: Vec<CallFrame>,
     stack: Vec<value::Value>,
-    stack_top: usize,
+    open_upvalues: Vec<std::rc::Rc<object::ObjUpvalue>>,
     globals: table::Table,
     strings: table::Table,
-    objects: Box<object::Obj>,
+    init_string: std::rc::Rc<object::ObjString>,
+    start_time: std::time::Instant,
+}
+
+impl Default for VM {
+    fn default() -> VM {
+        return VM::new();
+    }
 }
 
 impl VM {
     pub fn new() -> VM {
-        return VM {
-            chunk: chunk::Chunk::new(),
-            ip: Box::new(0 as u8),
+        let mut vm: VM = VM {
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
             stack: Vec::new(),
-            stack_top: 0,
             globals: table::Table::new(),
             strings: table::Table::new(),
-            objects: Box::new(object::Obj::new()),
+            init_string: std::rc::Rc::new(object::ObjString::create(String::new(), 0)),
+            start_time: std::time::Instant::now(),
         };
+        vm.init_string = object::copy_string(&mut vm, "init").as_string();
+
+        vm.define_native("clock", 0, native::clock);
+        return vm;
+    }
+
+    pub fn get_start_time(&self) -> std::time::Instant {
+        return self.start_time;
+    }
+
+    /// Binds a Rust function to a global name, so scripts can call it with
+    /// exactly `arity` arguments.
+    pub fn define_native(&mut self, name: &str, arity: i64, function: object::NativeFn) {
+        let name: std::rc::Rc<object::ObjString> = object::copy_string(self, name).as_string();
+        let native: object::ObjNative =
+            object::ObjNative::create(std::rc::Rc::clone(&name), arity, function);
+        self.globals.table_set(
+            name,
+            value::Value::create(
+                value::ValueType::ValObj,
+                value::Union::create_obj(object::Obj::Native(std::rc::Rc::new(native))),
+            ),
+        );
+    }
+
+    pub fn get_strings(&mut self) -> &mut table::Table {
+        return &mut self.strings;
+    }
+
+    fn push(&mut self, value: value::Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> value::Value {
+        return self.stack.pop().unwrap_or_default();
     }
 
-    fn byte_instruction(&self, name: &str, offset: i64) -> i64 {
-        let slot = self.chunk.get_code()[offset as usize + 1];
+    fn peek(&self, distance: usize) -> &value::Value {
+        return &self.stack[self.stack.len() - 1 - distance];
+    }
+
+    fn reset_stack(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        let last: usize = self.frames.len() - 1;
+        return &mut self.frames[last];
+    }
+
+    /// Reports a runtime error with a trace of the active calls, innermost
+    /// first.
+    fn runtime_error(&mut self, message: String) {
+        eprintln!("{}", message);
+        for frame in self.frames.iter().rev() {
+            let function: &object::ObjFunction = frame.closure.get_function();
+            let chunk: &chunk::Chunk = function.get_chunk();
+            let line: i64 = chunk.get_lines()[frame.ip - 1];
+            let column: i64 = chunk.get_columns()[frame.ip - 1];
+            match function.get_name() {
+                Some(name) => eprintln!("[line {}:{}] in {}()", line, column, name.get_chars()),
+                None => eprintln!("[line {}:{}] in script", line, column),
+            }
+        }
+        self.reset_stack();
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame: &mut CallFrame = self.frame();
+        let byte: u8 = frame.closure.get_function().get_chunk().get_code()[frame.ip];
+        frame.ip += 1;
+        return byte;
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let high: u8 = self.read_byte();
+        let low: u8 = self.read_byte();
+        return ((high as u16) << 8) | low as u16;
+    }
+
+    /// Reads a constant index, one byte wide or three bytes (high byte first)
+    /// for the long instruction forms.
+    fn read_index(&mut self, long: bool) -> usize {
+        if !long {
+            return self.read_byte() as usize;
+        }
+        let high: u8 = self.read_byte();
+        let middle: u8 = self.read_byte();
+        let low: u8 = self.read_byte();
+        return ((high as usize) << 16) | ((middle as usize) << 8) | low as usize;
+    }
+
+    fn read_constant(&mut self, long: bool) -> value::Value {
+        let index: usize = self.read_index(long);
+        let chunk: &chunk::Chunk = self.frame().closure.get_function().get_chunk();
+        return chunk.get_constants().get_value(index);
+    }
+
+    fn read_string(&mut self, long: bool) -> std::rc::Rc<object::ObjString> {
+        return self.read_constant(long).get_value().get_obj().as_string();
+    }
+
+    fn is_string(value: &value::Value) -> bool {
+        return value.get_value_type() == value::ValueType::ValObj
+            && value.get_value().get_obj().get_type() == object::ObjType::ObjectString;
+    }
+
+    fn is_class(value: &value::Value) -> bool {
+        return value.get_value_type() == value::ValueType::ValObj
+            && value.get_value().get_obj().get_type() == object::ObjType::ObjectClass;
+    }
+
+    fn is_instance(value: &value::Value) -> bool {
+        return value.get_value_type() == value::ValueType::ValObj
+            && value.get_value().get_obj().get_type() == object::ObjType::ObjectInstance;
+    }
+
+    fn check_arity(&mut self, arity: i64, arg_count: u8) -> bool {
+        if arg_count as i64 != arity {
+            self.runtime_error(format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            ));
+            return false;
+        }
+        return true;
+    }
+
+    fn call(&mut self, closure: std::rc::Rc<object::ObjClosure>, arg_count: u8) -> bool {
+        if !self.check_arity(closure.get_function().get_arity(), arg_count) {
+            return false;
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            self.runtime_error("Stack overflow.".to_string());
+            return false;
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count as usize - 1,
+        });
+        return true;
+    }
+
+    /// Runs a native function to completion and replaces the callee and its
+    /// arguments on the stack with the result.
+    fn call_native(&mut self, native: std::rc::Rc<object::ObjNative>, arg_count: u8) -> bool {
+        if !self.check_arity(native.get_arity(), arg_count) {
+            return false;
+        }
+
+        let args_start: usize = self.stack.len() - arg_count as usize;
+        let args: Vec<value::Value> = self.stack[args_start..].to_vec();
+        match (native.get_function())(self, &args) {
+            Ok(result) => {
+                self.stack.truncate(args_start - 1);
+                self.push(result);
+                return true;
+            }
+            Err(error) => {
+                self.runtime_error(error.get_message().to_string());
+                return false;
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: value::Value, arg_count: u8) -> bool {
+        if callee.get_value_type() == value::ValueType::ValObj {
+            match callee.get_value().get_obj() {
+                object::Obj::Closure(closure) => return self.call(closure, arg_count),
+                object::Obj::Native(native) => return self.call_native(native, arg_count),
+                object::Obj::Class(class) => {
+                    let receiver: usize = self.stack.len() - arg_count as usize - 1;
+                    let instance: object::Obj = object::Obj::Instance(std::rc::Rc::new(
+                        object::ObjInstance::create(std::rc::Rc::clone(&class)),
+                    ));
+                    self.stack[receiver] = value::Value::create(
+                        value::ValueType::ValObj,
+                        value::Union::create_obj(instance),
+                    );
+
+                    match class.get_method(&self.init_string) {
+                        Some(initializer) => {
+                            return self.call(initializer.get_value().get_obj().as_closure(), arg_count)
+                        }
+                        None if arg_count != 0 => {
+                            self.runtime_error(format!(
+                                "Expected 0 arguments but got {}.",
+                                arg_count
+                            ));
+                            return false;
+                        }
+                        None => return true,
+                    }
+                }
+                object::Obj::BoundMethod(bound) => {
+                    let receiver: usize = self.stack.len() - arg_count as usize - 1;
+                    self.stack[receiver] = bound.get_receiver().clone();
+                    return self.call(std::rc::Rc::clone(bound.get_method()), arg_count);
+                }
+                _ => {}
+            }
+        }
+        self.runtime_error("Can only call functions and classes.".to_string());
+        return false;
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: std::rc::Rc<object::ObjClass>,
+        name: std::rc::Rc<object::ObjString>,
+        arg_count: u8,
+    ) -> bool {
+        match class.get_method(&name) {
+            Some(method) => return self.call(method.get_value().get_obj().as_closure(), arg_count),
+            None => {
+                self.runtime_error(format!("Undefined property '{}'.", name.get_chars()));
+                return false;
+            }
+        }
+    }
+
+    /// Calls a method straight off an instance, without creating the bound
+    /// method that a separate property access and call would need.
+    fn invoke(&mut self, name: std::rc::Rc<object::ObjString>, arg_count: u8) -> bool {
+        let receiver: value::Value = self.peek(arg_count as usize).clone();
+        if !VM::is_instance(&receiver) {
+            self.runtime_error("Only instances have methods.".to_string());
+            return false;
+        }
+
+        let instance: std::rc::Rc<object::ObjInstance> = receiver.get_value().get_obj().as_instance();
+        if let Some(field) = instance.get_field(&name) {
+            let slot: usize = self.stack.len() - arg_count as usize - 1;
+            self.stack[slot] = field.clone();
+            return self.call_value(field, arg_count);
+        }
+
+        return self.invoke_from_class(std::rc::Rc::clone(instance.get_class()), name, arg_count);
+    }
+
+    /// Replaces the instance on top of the stack with its method `name`,
+    /// bound to that instance.
+    fn bind_method(
+        &mut self,
+        class: std::rc::Rc<object::ObjClass>,
+        name: std::rc::Rc<object::ObjString>,
+    ) -> bool {
+        let method: value::Value = match class.get_method(&name) {
+            Some(method) => method,
+            None => {
+                self.runtime_error(format!("Undefined property '{}'.", name.get_chars()));
+                return false;
+            }
+        };
+
+        let bound: object::ObjBoundMethod = object::ObjBoundMethod::create(
+            self.pop(),
+            method.get_value().get_obj().as_closure(),
+        );
+        self.push(value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(object::Obj::BoundMethod(std::rc::Rc::new(bound))),
+        ));
+        return true;
+    }
+
+    fn define_method(&mut self, name: std::rc::Rc<object::ObjString>) {
+        let method: value::Value = self.peek(0).clone();
+        let class: std::rc::Rc<object::ObjClass> = self.peek(1).get_value().get_obj().as_class();
+        class.set_method(name, method);
+        self.pop();
+    }
+
+    /// Returns the upvalue for a stack slot, reusing an open one so that
+    /// closures capturing the same variable share it.
+    fn capture_upvalue(&mut self, slot: usize) -> std::rc::Rc<object::ObjUpvalue> {
+        for upvalue in self.open_upvalues.iter() {
+            if upvalue.get_slot() == Some(slot) {
+                return std::rc::Rc::clone(upvalue);
+            }
+        }
+
+        let upvalue: std::rc::Rc<object::ObjUpvalue> =
+            std::rc::Rc::new(object::ObjUpvalue::create(slot));
+        self.open_upvalues.push(std::rc::Rc::clone(&upvalue));
+        return upvalue;
+    }
+
+    /// Closes every open upvalue pointing at `last` or above, moving the
+    /// captured values off the stack.
+    fn close_upvalues(&mut self, last: usize) {
+        let stack: &Vec<value::Value> = &self.stack;
+        self.open_upvalues.retain(|upvalue| match upvalue.get_slot() {
+            Some(slot) if slot >= last => {
+                upvalue.close(stack[slot].clone());
+                return false;
+            }
+            _ => return true,
+        });
+    }
+
+    fn concatenate(&mut self) {
+        let b: std::rc::Rc<object::ObjString> = self.pop().get_value().get_obj().as_string();
+        let a: std::rc::Rc<object::ObjString> = self.pop().get_value().get_obj().as_string();
+        let mut chars: String = String::with_capacity(a.get_length() + b.get_length());
+        chars.push_str(a.get_chars());
+        chars.push_str(b.get_chars());
+        let result: object::Obj = object::take_string(self, chars);
+        self.push(value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(result),
+        ));
+    }
+
+    fn byte_instruction(&self, chunk: &chunk::Chunk, name: &str, offset: i64) -> i64 {
+        let slot = chunk.get_code()[offset as usize + 1];
         println!("{} {}", name, slot);
         return offset + 2;
     }
@@ -40,54 +404,82 @@ impl VM {
         return offset + 1;
     }
 
-    fn constant_instruction(&self, name: &str, offset: i64) -> i64 {
-        let constant: u8 = self.chunk.get_code()[offset as usize + 1];
+    fn constant_instruction(&self, chunk: &chunk::Chunk, name: &str, offset: i64) -> i64 {
+        let (constant, width): (usize, usize) = chunk.get_constant_operand(offset as usize);
         println!(
             "{} {} {:#?}",
             name,
             constant,
-            self.chunk.get_constants().get_values()[constant as usize].print()
+            chunk.get_constants().get_values()[constant].print()
         );
-        return offset + 2;
+        return offset + 1 + width as i64;
     }
 
-    fn jump_instruction(&self, name: &str, sign: i64, offset: i64) -> i64 {
-        let mut jump: u16 = (self.chunk.get_code()[offset as usize + 1] as u16) << 8;
-        jump |= (self.chunk.get_code()[offset as usize + 2]) as u16;
+    fn jump_instruction(&self, chunk: &chunk::Chunk, name: &str, sign: i64, offset: i64) -> i64 {
+        let mut jump: u16 = (chunk.get_code()[offset as usize + 1] as u16) << 8;
+        jump |= (chunk.get_code()[offset as usize + 2]) as u16;
         println!("{} {} -> {}", name, offset, offset + 3 + sign * jump as i64);
         return offset + 3;
     }
 
-    fn disassemble_instruction(&self, mut offset: i64, set_offset: bool) -> i64 {
-        if !set_offset {
-            offset = (*self.ip - self.chunk.get_code()[0]) as i64;
+    fn invoke_instruction(&self, chunk: &chunk::Chunk, name: &str, offset: i64) -> i64 {
+        let (constant, width): (usize, usize) = chunk.get_constant_operand(offset as usize);
+        let arg_count: u8 = chunk.get_code()[offset as usize + 1 + width];
+        println!(
+            "{} ({} args) {} {:#?}",
+            name,
+            arg_count,
+            constant,
+            chunk.get_constants().get_values()[constant].print()
+        );
+        return offset + 2 + width as i64;
+    }
+
+    fn closure_instruction(&self, chunk: &chunk::Chunk, name: &str, offset: i64) -> i64 {
+        let (constant, width): (usize, usize) = chunk.get_constant_operand(offset as usize);
+        let mut offset: usize = offset as usize + 1 + width;
+        let function: value::Value = chunk.get_constants().get_value(constant);
+        println!("{} {} {}", name, constant, function.print());
+
+        let upvalue_count: i64 = function.get_value().get_obj().as_function().get_upvalue_count();
+        for _ in 0..upvalue_count {
+            let is_local: u8 = chunk.get_code()[offset];
+            let index: u8 = chunk.get_code()[offset + 1];
+            let kind: &str = if is_local == 1 { "local" } else { "upvalue" };
+            println!("{}\t|                     {} {}", offset, kind, index);
+            offset += 2;
         }
+        return offset as i64;
+    }
+
+    fn disassemble_instruction(&self, chunk: &chunk::Chunk, offset: i64) -> i64 {
         print!("{}\t", offset);
 
         if offset > 0
-            && self.chunk.get_lines()[offset as usize]
-                == self.chunk.get_lines()[offset as usize - 1]
+            && chunk.get_lines()[offset as usize]
+                == chunk.get_lines()[offset as usize - 1]
         {
             print!("| ");
         } else {
-            print!("{} ", self.chunk.get_lines()[offset as usize]);
+            print!("{} ", chunk.get_lines()[offset as usize]);
         }
 
-        let instruction: chunk::OpCode = self.chunk.get_code()[offset as usize].into();
+        let instruction: chunk::OpCode = chunk.get_code()[offset as usize].into();
         match instruction {
-            chunk::OpCode::OpConstant => return self.constant_instruction("OpConstant", offset),
+            chunk::OpCode::OpConstant => return self.constant_instruction(chunk, "OpConstant", offset),
             chunk::OpCode::OpNil => return self.simple_instruction("OpNil", offset),
             chunk::OpCode::OpTrue => return self.simple_instruction("OpTrue", offset),
             chunk::OpCode::OpFalse => return self.simple_instruction("OpFalse", offset),
             chunk::OpCode::OpPop => return self.simple_instruction("OpPop", offset),
-            chunk::OpCode::OpGetLocal => return self.byte_instruction("OpGetLocal", offset),
-            chunk::OpCode::OpSetLocal => return self.byte_instruction("OpSetLocal", offset),
-            chunk::OpCode::OpGetGlobal => return self.constant_instruction("OpGetGlobal", offset),
+            chunk::OpCode::OpGetLocal => return self.byte_instruction(chunk, "OpGetLocal", offset),
+            chunk::OpCode::OpSetLocal => return self.byte_instruction(chunk, "OpSetLocal", offset),
+            chunk::OpCode::OpGetGlobal => return self.constant_instruction(chunk, "OpGetGlobal", offset),
             chunk::OpCode::OpDefineGlobal => {
-                return self.constant_instruction("OpDefineGlobal", offset)
+                return self.constant_instruction(chunk, "OpDefineGlobal", offset)
             }
-            chunk::OpCode::OpSetGlobal => return self.constant_instruction("OpSetGlobal", offset),
+            chunk::OpCode::OpSetGlobal => return self.constant_instruction(chunk, "OpSetGlobal", offset),
             chunk::OpCode::OpEqual => return self.simple_instruction("OpEqual", offset),
+            chunk::OpCode::OpNotEqual => return self.simple_instruction("OpNotEqual", offset),
             chunk::OpCode::OpGreater => return self.simple_instruction("OpGreater", offset),
             chunk::OpCode::OpLess => return self.simple_instruction("OpLess", offset),
             chunk::OpCode::OpAdd => return self.simple_instruction("OpAdd", offset),
@@ -97,291 +489,418 @@ impl VM {
             chunk::OpCode::OpNot => return self.simple_instruction("OpNot", offset),
             chunk::OpCode::OpNegate => return self.simple_instruction("OpNegate", offset),
             chunk::OpCode::OpPrint => return self.simple_instruction("OpPrint", offset),
-            chunk::OpCode::OpJump => return self.jump_instruction("OpJump", 1, offset),
+            chunk::OpCode::OpJump => return self.jump_instruction(chunk, "OpJump", 1, offset),
             chunk::OpCode::OpJumpIfFalse => {
-                return self.jump_instruction("OpJumpIfFalse", 1, offset)
+                return self.jump_instruction(chunk, "OpJumpIfFalse", 1, offset)
             }
-            chunk::OpCode::OpLoop => return self.jump_instruction("OpLoop", -1, offset),
+            chunk::OpCode::OpLoop => return self.jump_instruction(chunk, "OpLoop", -1, offset),
             chunk::OpCode::OpReturn => return self.simple_instruction("OpReturn", offset),
+            chunk::OpCode::OpStringify => return self.simple_instruction("OpStringify", offset),
+            chunk::OpCode::OpCall => return self.byte_instruction(chunk, "OpCall", offset),
+            chunk::OpCode::OpGetUpvalue => {
+                return self.byte_instruction(chunk, "OpGetUpvalue", offset)
+            }
+            chunk::OpCode::OpSetUpvalue => {
+                return self.byte_instruction(chunk, "OpSetUpvalue", offset)
+            }
+            chunk::OpCode::OpClosure => return self.closure_instruction(chunk, "OpClosure", offset),
+            chunk::OpCode::OpCloseUpvalue => {
+                return self.simple_instruction("OpCloseUpvalue", offset)
+            }
+            chunk::OpCode::OpGetProperty => {
+                return self.constant_instruction(chunk, "OpGetProperty", offset)
+            }
+            chunk::OpCode::OpSetProperty => {
+                return self.constant_instruction(chunk, "OpSetProperty", offset)
+            }
+            chunk::OpCode::OpClass => return self.constant_instruction(chunk, "OpClass", offset),
+            chunk::OpCode::OpMethod => return self.constant_instruction(chunk, "OpMethod", offset),
+            chunk::OpCode::OpInvoke => return self.invoke_instruction(chunk, "OpInvoke", offset),
+            chunk::OpCode::OpInherit => return self.simple_instruction("OpInherit", offset),
+            chunk::OpCode::OpGetSuper => {
+                return self.constant_instruction(chunk, "OpGetSuper", offset)
+            }
+            chunk::OpCode::OpSuperInvoke => {
+                return self.invoke_instruction(chunk, "OpSuperInvoke", offset)
+            }
+            chunk::OpCode::OpConstantLong => {
+                return self.constant_instruction(chunk, "OpConstantLong", offset)
+            }
+            chunk::OpCode::OpGetGlobalLong => {
+                return self.constant_instruction(chunk, "OpGetGlobalLong", offset)
+            }
+            chunk::OpCode::OpDefineGlobalLong => {
+                return self.constant_instruction(chunk, "OpDefineGlobalLong", offset)
+            }
+            chunk::OpCode::OpSetGlobalLong => {
+                return self.constant_instruction(chunk, "OpSetGlobalLong", offset)
+            }
+            chunk::OpCode::OpClosureLong => {
+                return self.closure_instruction(chunk, "OpClosureLong", offset)
+            }
+            chunk::OpCode::OpGetPropertyLong => {
+                return self.constant_instruction(chunk, "OpGetPropertyLong", offset)
+            }
+            chunk::OpCode::OpSetPropertyLong => {
+                return self.constant_instruction(chunk, "OpSetPropertyLong", offset)
+            }
+            chunk::OpCode::OpClassLong => {
+                return self.constant_instruction(chunk, "OpClassLong", offset)
+            }
+            chunk::OpCode::OpMethodLong => {
+                return self.constant_instruction(chunk, "OpMethodLong", offset)
+            }
+            chunk::OpCode::OpInvokeLong => {
+                return self.invoke_instruction(chunk, "OpInvokeLong", offset)
+            }
+            chunk::OpCode::OpGetSuperLong => {
+                return self.constant_instruction(chunk, "OpGetSuperLong", offset)
+            }
+            chunk::OpCode::OpSuperInvokeLong => {
+                return self.invoke_instruction(chunk, "OpSuperInvokeLong", offset)
+            }
         }
     }
 
     fn run(&mut self) -> InterpretResult {
         loop {
-            println!("          ");
-            let mut slot = 0;
-            while slot < self.stack_top {
-                println!("[ {} ]", self.stack[slot].print());
-                slot += 1;
+            #[cfg(feature = "debug_trace_execution")]
+            {
+                print!("          ");
+                for slot in self.stack.iter() {
+                    print!("[ {} ]", slot.print());
+                }
+                println!();
+                let frame: &CallFrame = &self.frames[self.frames.len() - 1];
+                self.disassemble_instruction(frame.closure.get_function().get_chunk(), frame.ip as i64);
             }
 
-            self.disassemble_instruction(0, false);
-
-            let instruction: chunk::OpCode = (*self.ip).into();
-            *self.ip += 1;
-
+            let instruction: chunk::OpCode = self.read_byte().into();
             match instruction {
-                chunk::OpCode::OpConstant => {
-                    self.stack[self.stack_top] =
-                        self.chunk.get_constants().get_value(*self.ip as usize);
-                    self.stack_top += 1;
-                    *self.ip += 1;
-                    break;
-                }
-                chunk::OpCode::OpNil => {
-                    self.stack[self.stack_top] =
-                        value::Value::create(value::ValueType::ValNil, value::Union::create_num(0));
-                    *self.ip += 1;
-                    break;
-                }
-                chunk::OpCode::OpTrue => {
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValBool,
-                        value::Union::create_bool(true),
-                    );
-                    *self.ip += 1;
-                    break;
-                }
-                chunk::OpCode::OpFalse => {
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValBool,
-                        value::Union::create_bool(false),
-                    );
-                    *self.ip += 1;
-                    break;
+                chunk::OpCode::OpConstant | chunk::OpCode::OpConstantLong => {
+                    let constant: value::Value = self.read_constant(instruction.is_long());
+                    self.push(constant);
                 }
+                chunk::OpCode::OpNil => self.push(value::Value::new()),
+                chunk::OpCode::OpTrue => self.push(value::Value::create(
+                    value::ValueType::ValBool,
+                    value::Union::create_bool(true),
+                )),
+                chunk::OpCode::OpFalse => self.push(value::Value::create(
+                    value::ValueType::ValBool,
+                    value::Union::create_bool(false),
+                )),
                 chunk::OpCode::OpPop => {
-                    *self.ip -= 1;
-                    break;
+                    self.pop();
                 }
                 chunk::OpCode::OpGetLocal => {
-                    let slot: u8 = *self.ip;
-                    self.stack[self.stack_top] = self.stack[slot as usize];
-                    *self.ip += 1;
-                    break;
+                    let slot: u8 = self.read_byte();
+                    let slots: usize = self.frame().slots;
+                    self.push(self.stack[slots + slot as usize].clone());
                 }
                 chunk::OpCode::OpSetLocal => {
-                    let slot: u8 = *self.ip;
-                    self.stack[self.stack_top] = self.stack[0];
-                    *self.ip += 1;
-                    break;
-                }
-                chunk::OpCode::OpGetGlobal => {
-                    let name: object::ObjString = object::ObjString::copy(self.chunk.get_constants().get_value(*self.ip as usize).get_value().get_obj());
-                    self.stack_top += 1;
-
-                    let value: value::Value = value::Value::new();
-                    if !self.globals.table_get(name, value) {
-                        eprintln!("RUNTIME ERROR");
-                        self.stack_top = 0; 
-                        return InterpretResult::InterpretRuntimeError;
+                    let slot: u8 = self.read_byte();
+                    let slots: usize = self.frame().slots;
+                    self.stack[slots + slot as usize] = self.peek(0).clone();
+                }
+                chunk::OpCode::OpGetGlobal | chunk::OpCode::OpGetGlobalLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    match self.globals.table_get(&name) {
+                        Some(value) => self.push(value),
+                        None => {
+                            self.runtime_error(format!(
+                                "Undefined variable '{}'.",
+                                name.get_chars()
+                            ));
+                            return InterpretResult::InterpretRuntimeError;
+                        }
                     }
-                    self.stack[self.stack_top] = value;
-                    break;
-                },
-                chunk::OpCode::OpDefineGlobal => {
-                    let name: object::ObjString = object::ObjString::copy(self.chunk.get_constants().get_value(*self.ip as usize).get_value().get_obj());
-
-                    self.chunk.print();
-
-                    self.stack_top += 1;
-                    
-                    self.globals.table_set(name, self.stack[0]);
-
-                    self.stack_top -= 1;
-                },
-                chunk::OpCode::OpSetGlobal => {
-                    let name: object::ObjString = object::ObjString::copy(self.chunk.get_constants().get_value(*self.ip as usize).get_value().get_obj());
-                    if self.globals.table_set(name, self.stack[0]) {
-                        self.globals.table_delete(name);
-                        eprintln!("RUNTIME ERROR");
+                }
+                chunk::OpCode::OpDefineGlobal | chunk::OpCode::OpDefineGlobalLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let value: value::Value = self.peek(0).clone();
+                    self.globals.table_set(name, value);
+                    self.pop();
+                }
+                chunk::OpCode::OpSetGlobal | chunk::OpCode::OpSetGlobalLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let value: value::Value = self.peek(0).clone();
+                    if self.globals.table_set(std::rc::Rc::clone(&name), value) {
+                        self.globals.table_delete(&name);
+                        self.runtime_error(format!("Undefined variable '{}'.", name.get_chars()));
                         return InterpretResult::InterpretRuntimeError;
                     }
-                    break;
-                },
+                }
                 chunk::OpCode::OpEqual => {
-                    let a: value::Value = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    let b: value::Value = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
+                    let b: value::Value = self.pop();
+                    let a: value::Value = self.pop();
+                    self.push(value::Value::create(
                         value::ValueType::ValBool,
-                        value::Union::create_bool(a.equal(b)),
-                    );
-
-                    self.stack_top += 1;
-                    break;
+                        value::Union::create_bool(a.equal(&b)),
+                    ));
+                }
+                chunk::OpCode::OpNotEqual => {
+                    let b: value::Value = self.pop();
+                    let a: value::Value = self.pop();
+                    self.push(value::Value::create(
+                        value::ValueType::ValBool,
+                        value::Union::create_bool(!a.equal(&b)),
+                    ));
                 }
                 chunk::OpCode::OpGreater => {
-                    if (self.stack[0].get_value_type() != value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() != value::ValueType::ValNumber)
+                    binary_op!(self, value::ValueType::ValBool, value::Union::create_bool, >)
+                }
+                chunk::OpCode::OpLess => {
+                    binary_op!(self, value::ValueType::ValBool, value::Union::create_bool, <)
+                }
+                chunk::OpCode::OpAdd => {
+                    if VM::is_string(self.peek(0)) && VM::is_string(self.peek(1)) {
+                        self.concatenate();
+                    } else if self.peek(0).get_value_type() == value::ValueType::ValNumber
+                        && self.peek(1).get_value_type() == value::ValueType::ValNumber
                     {
+                        binary_op!(self, value::ValueType::ValNumber, value::Union::create_num, +)
+                    } else {
+                        self.runtime_error(
+                            "Operands must be two numbers or two strings.".to_string(),
+                        );
                         return InterpretResult::InterpretRuntimeError;
                     }
-
-                    let b = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-
-                    let a = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
+                }
+                chunk::OpCode::OpSubtract => {
+                    binary_op!(self, value::ValueType::ValNumber, value::Union::create_num, -)
+                }
+                chunk::OpCode::OpMultiply => {
+                    binary_op!(self, value::ValueType::ValNumber, value::Union::create_num, *)
+                }
+                chunk::OpCode::OpDivide => {
+                    binary_op!(self, value::ValueType::ValNumber, value::Union::create_num, /)
+                }
+                chunk::OpCode::OpNot => {
+                    let value: value::Value = self.pop();
+                    self.push(value::Value::create(
                         value::ValueType::ValBool,
-                        value::Union::create_bool(
-                            a.get_value().get_number() > b.get_value().get_number(),
-                        ),
-                    );
-                    self.stack_top += 1;
-                    break;
+                        value::Union::create_bool(value.is_falsey()),
+                    ));
                 }
-                chunk::OpCode::OpLess => {
-                    if (self.stack[0].get_value_type() != value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() != value::ValueType::ValNumber)
-                    {
+                chunk::OpCode::OpNegate => {
+                    if self.peek(0).get_value_type() != value::ValueType::ValNumber {
+                        self.runtime_error("Operand must be a number.".to_string());
                         return InterpretResult::InterpretRuntimeError;
                     }
-
-                    let b = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-
-                    let a = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValBool,
-                        value::Union::create_bool(
-                            a.get_value().get_number() < b.get_value().get_number(),
-                        ),
-                    );
-                    self.stack_top += 1;
-                    break;
+                    let number: f64 = self.pop().get_value().get_number();
+                    self.push(value::Value::create(
+                        value::ValueType::ValNumber,
+                        value::Union::create_num(-number),
+                    ));
                 }
-                chunk::OpCode::OpAdd => {
-                    if (self.stack[0].get_value_type() == value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() == value::ValueType::ValNumber)
-                    {
-                        let b = self.stack[self.stack_top];
-                        self.stack_top -= 1;
-
-                        let a = self.stack[self.stack_top];
-                        self.stack_top -= 1;
-                        self.stack[self.stack_top] = value::Value::create(
-                            value::ValueType::ValNumber,
-                            value::Union::create_num(
-                                a.get_value().get_number() + b.get_value().get_number(),
-                            ),
-                        );
-                        self.stack_top += 1;
-                    } else if ((self.stack[0].get_value_type() == value::ValueType::ValObj)
-                        || (self.stack[1].get_value_type() == value::ValueType::ValObj))
-                        && (self.stack[0].get_value().get_obj().get_type()
-                            == object::ObjType::ObjectString)
-                    {
-                        todo!();
-                    } else {
+                chunk::OpCode::OpPrint => {
+                    println!("{}", self.pop().print());
+                }
+                chunk::OpCode::OpJump => {
+                    let offset: u16 = self.read_short();
+                    self.frame().ip += offset as usize;
+                }
+                chunk::OpCode::OpJumpIfFalse => {
+                    let offset: u16 = self.read_short();
+                    if self.peek(0).is_falsey() {
+                        self.frame().ip += offset as usize;
+                    }
+                }
+                chunk::OpCode::OpLoop => {
+                    let offset: u16 = self.read_short();
+                    self.frame().ip -= offset as usize;
+                }
+                chunk::OpCode::OpCall => {
+                    let arg_count: u8 = self.read_byte();
+                    let callee: value::Value = self.peek(arg_count as usize).clone();
+                    if !self.call_value(callee, arg_count) {
                         return InterpretResult::InterpretRuntimeError;
                     }
-                    break;
                 }
-                chunk::OpCode::OpSubtract => {
-                    if (self.stack[0].get_value_type() != value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() != value::ValueType::ValNumber)
-                    {
+                chunk::OpCode::OpGetUpvalue => {
+                    let slot: u8 = self.read_byte();
+                    let upvalue: std::rc::Rc<object::ObjUpvalue> =
+                        std::rc::Rc::clone(&self.frame().closure.get_upvalues()[slot as usize]);
+                    let value: value::Value = match upvalue.get_slot() {
+                        Some(stack_slot) => self.stack[stack_slot].clone(),
+                        None => upvalue.get_closed().unwrap_or_default(),
+                    };
+                    self.push(value);
+                }
+                chunk::OpCode::OpSetUpvalue => {
+                    let slot: u8 = self.read_byte();
+                    let upvalue: std::rc::Rc<object::ObjUpvalue> =
+                        std::rc::Rc::clone(&self.frame().closure.get_upvalues()[slot as usize]);
+                    let value: value::Value = self.peek(0).clone();
+                    match upvalue.get_slot() {
+                        Some(stack_slot) => self.stack[stack_slot] = value,
+                        None => upvalue.close(value),
+                    }
+                }
+                chunk::OpCode::OpClosure | chunk::OpCode::OpClosureLong => {
+                    let function: std::rc::Rc<object::ObjFunction> =
+                        self.read_constant(instruction.is_long()).get_value().get_obj().as_function();
+                    let mut upvalues: Vec<std::rc::Rc<object::ObjUpvalue>> = Vec::new();
+                    for _ in 0..function.get_upvalue_count() {
+                        let is_local: u8 = self.read_byte();
+                        let index: u8 = self.read_byte();
+                        if is_local == 1 {
+                            let slot: usize = self.frame().slots + index as usize;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(std::rc::Rc::clone(
+                                &self.frame().closure.get_upvalues()[index as usize],
+                            ));
+                        }
+                    }
+
+                    let closure: object::Obj =
+                        object::Obj::Closure(std::rc::Rc::new(object::ObjClosure::create(
+                            function, upvalues,
+                        )));
+                    self.push(value::Value::create(
+                        value::ValueType::ValObj,
+                        value::Union::create_obj(closure),
+                    ));
+                }
+                chunk::OpCode::OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                chunk::OpCode::OpGetProperty | chunk::OpCode::OpGetPropertyLong => {
+                    if !VM::is_instance(self.peek(0)) {
+                        self.runtime_error("Only instances have properties.".to_string());
                         return InterpretResult::InterpretRuntimeError;
                     }
 
-                    let b = self.stack[self.stack_top];
-                    self.stack_top -= 1;
+                    let instance: std::rc::Rc<object::ObjInstance> =
+                        self.peek(0).get_value().get_obj().as_instance();
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
 
-                    let a = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValNumber,
-                        value::Union::create_num(
-                            a.get_value().get_number() - b.get_value().get_number(),
-                        ),
-                    );
-                    self.stack_top += 1;
-                    break;
+                    if let Some(value) = instance.get_field(&name) {
+                        self.pop();
+                        self.push(value);
+                    } else if !self.bind_method(std::rc::Rc::clone(instance.get_class()), name) {
+                        return InterpretResult::InterpretRuntimeError;
+                    }
                 }
-                chunk::OpCode::OpMultiply => {
-                    if (self.stack[0].get_value_type() != value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() != value::ValueType::ValNumber)
-                    {
+                chunk::OpCode::OpSetProperty | chunk::OpCode::OpSetPropertyLong => {
+                    if !VM::is_instance(self.peek(1)) {
+                        self.runtime_error("Only instances have fields.".to_string());
                         return InterpretResult::InterpretRuntimeError;
                     }
 
-                    let b = self.stack[self.stack_top];
-                    self.stack_top -= 1;
+                    let instance: std::rc::Rc<object::ObjInstance> =
+                        self.peek(1).get_value().get_obj().as_instance();
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    instance.set_field(name, self.peek(0).clone());
 
-                    let a = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValNumber,
-                        value::Union::create_num(
-                            a.get_value().get_number() * b.get_value().get_number(),
-                        ),
-                    );
-                    self.stack_top += 1;
-                    break;
+                    let value: value::Value = self.pop();
+                    self.pop();
+                    self.push(value);
                 }
-                chunk::OpCode::OpDivide => {
-                    if (self.stack[0].get_value_type() != value::ValueType::ValNumber)
-                        || (self.stack[1].get_value_type() != value::ValueType::ValNumber)
-                    {
+                chunk::OpCode::OpClass | chunk::OpCode::OpClassLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let class: object::Obj =
+                        object::Obj::Class(std::rc::Rc::new(object::ObjClass::create(name)));
+                    self.push(value::Value::create(
+                        value::ValueType::ValObj,
+                        value::Union::create_obj(class),
+                    ));
+                }
+                chunk::OpCode::OpMethod | chunk::OpCode::OpMethodLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    self.define_method(name);
+                }
+                chunk::OpCode::OpInvoke | chunk::OpCode::OpInvokeLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let arg_count: u8 = self.read_byte();
+                    if !self.invoke(name, arg_count) {
+                        return InterpretResult::InterpretRuntimeError;
+                    }
+                }
+                chunk::OpCode::OpInherit => {
+                    if !VM::is_class(self.peek(1)) {
+                        self.runtime_error("Superclass must be a class.".to_string());
                         return InterpretResult::InterpretRuntimeError;
                     }
 
-                    let b = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-
-                    let a = self.stack[self.stack_top];
-                    self.stack_top -= 1;
-                    self.stack[self.stack_top] = value::Value::create(
-                        value::ValueType::ValNumber,
-                        value::Union::create_num(
-                            a.get_value().get_number() / b.get_value().get_number(),
-                        ),
-                    );
-                    self.stack_top += 1;
-                    break;
+                    let superclass: std::rc::Rc<object::ObjClass> =
+                        self.peek(1).get_value().get_obj().as_class();
+                    let subclass: std::rc::Rc<object::ObjClass> =
+                        self.peek(0).get_value().get_obj().as_class();
+                    subclass.inherit(&superclass);
+                    self.pop();
                 }
+                chunk::OpCode::OpGetSuper | chunk::OpCode::OpGetSuperLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let superclass: std::rc::Rc<object::ObjClass> =
+                        self.pop().get_value().get_obj().as_class();
+                    if !self.bind_method(superclass, name) {
+                        return InterpretResult::InterpretRuntimeError;
+                    }
+                }
+                chunk::OpCode::OpSuperInvoke | chunk::OpCode::OpSuperInvokeLong => {
+                    let name: std::rc::Rc<object::ObjString> = self.read_string(instruction.is_long());
+                    let arg_count: u8 = self.read_byte();
+                    let superclass: std::rc::Rc<object::ObjClass> =
+                        self.pop().get_value().get_obj().as_class();
+                    if !self.invoke_from_class(superclass, name, arg_count) {
+                        return InterpretResult::InterpretRuntimeError;
+                    }
+                }
+                chunk::OpCode::OpReturn => {
+                    let result: value::Value = self.pop();
+                    let slots: usize = self.frame().slots;
+                    self.close_upvalues(slots);
+                    let frame: Option<CallFrame> = self.frames.pop();
+                    if self.frames.is_empty() {
+                        self.pop();
+                        return InterpretResult::InterpretOk;
+                    }
 
-                chunk::OpCode::OpNot => todo!(),
-                chunk::OpCode::OpNegate => todo!(),
-                chunk::OpCode::OpPrint => {
-                    self.stack[self.stack_top].print();
-                    break;
+                    if let Some(frame) = frame {
+                        self.stack.truncate(frame.slots);
+                    }
+                    self.push(result);
+                }
+                chunk::OpCode::OpStringify => {
+                    if !VM::is_string(self.peek(0)) {
+                        let chars: String = self.pop().print();
+                        let string: object::Obj = object::take_string(self, chars);
+                        self.push(value::Value::create(
+                            value::ValueType::ValObj,
+                            value::Union::create_obj(string),
+                        ));
+                    }
                 }
-                chunk::OpCode::OpJump => todo!(),
-                chunk::OpCode::OpJumpIfFalse => todo!(),
-                chunk::OpCode::OpLoop => todo!(),
-                chunk::OpCode::OpReturn => return InterpretResult::InterpretOk,
             }
         }
-        return InterpretResult::InterpretOk;
     }
 
-    pub fn interpret(
-        &mut self,
-        source: String,
-        rules: &std::collections::HashMap<scanner::TokenType, compiler::ParseRule>,
-    ) -> InterpretResult {
-        let chunk: chunk::Chunk = chunk::Chunk::new();
-        if !compiler::compile(source, self, &chunk, rules) {
-            return InterpretResult::InterpretCompileError;
-        }
-        self.chunk = chunk;
-        self.ip = Box::new(self.chunk.get_code()[0]);
+    /// Runs a compiled script from the top.
+    pub fn execute(&mut self, compiled: compiler::CompiledFunction) -> InterpretResult {
+        let function: std::rc::Rc<object::ObjFunction> = std::rc::Rc::clone(compiled.get_function());
+
+        let closure: std::rc::Rc<object::ObjClosure> =
+            std::rc::Rc::new(object::ObjClosure::create(function, Vec::new()));
+        self.push(value::Value::create(
+            value::ValueType::ValObj,
+            value::Union::create_obj(object::Obj::Closure(std::rc::Rc::clone(&closure))),
+        ));
+        self.call(closure, 0);
+
         let result: InterpretResult = self.run();
         return result;
     }
 
-    pub fn disassemble_chunk(&mut self, chunk: chunk::Chunk, code: &str) {
+    pub fn disassemble_chunk(&self, chunk: &chunk::Chunk, code: &str) {
         println!("== {} ==", code);
-        self.chunk = chunk; 
 
         let mut offset = 0;
-        for mut offset in 0..chunk.get_count() {
-           offset = self.disassemble_instruction(offset, true); 
+        while offset < chunk.get_count() {
+            offset = self.disassemble_instruction(chunk, offset);
         }
     }
 }
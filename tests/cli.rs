@@ -0,0 +1,131 @@
+//! Runs the `rox` binary on small scripts written to a scratch directory and
+//! checks its output and exit code.
+
+#![allow(clippy::needless_return)]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn write_script(name: &str, contents: &[u8]) -> PathBuf {
+    let path: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, contents).expect("Error writing test script");
+    return path;
+}
+
+fn rox(args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_rox"))
+        .args(args)
+        .output()
+        .expect("Error running rox");
+}
+
+fn stdout(output: &Output) -> String {
+    return String::from_utf8_lossy(&output.stdout).to_string();
+}
+
+#[test]
+fn tokens_lists_every_token() {
+    let path: PathBuf = write_script("tokens.rox", b"print \"hi\";\nx @");
+    let output: Output = rox(&["tokens", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            "1:1\tTokenPrint\t\"print\"\t0..5\n",
+            "1:7\tTokenString\t\"\\\"hi\\\"\"\t6..10\n",
+            "1:11\tTokenSemicolon\t\";\"\t10..11\n",
+            "2:1\tTokenIdentifier\t\"x\"\t12..13\n",
+            "2:3\tTokenError\t\"@\"\t14..15\tUnexpected character.\n",
+            "2:4\tTokenEof\t\"\"\t15..15\n",
+        )
+    );
+}
+
+#[test]
+fn tokens_json_prints_one_object_per_line() {
+    let path: PathBuf = write_script("tokens_json.rox", b"print \"a\tb\";\n@");
+    let output: Output = rox(&["tokens", "--json", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            r#"{"type":"TokenPrint","lexeme":"print","line":1,"column":1,"start":0,"end":5}"#,
+            "\n",
+            r#"{"type":"TokenString","lexeme":"\"a\tb\"","line":1,"column":7,"start":6,"end":11}"#,
+            "\n",
+            r#"{"type":"TokenSemicolon","lexeme":";","line":1,"column":12,"start":11,"end":12}"#,
+            "\n",
+            r#"{"type":"TokenError","lexeme":"@","line":2,"column":1,"start":13,"end":14,"message":"Unexpected character."}"#,
+            "\n",
+            r#"{"type":"TokenEof","lexeme":"","line":2,"column":2,"start":14,"end":14}"#,
+            "\n",
+        )
+    );
+}
+
+#[test]
+fn tokens_without_a_path_prints_usage() {
+    let output: Output = rox(&["tokens"]);
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("rox tokens [--json] <path>"));
+}
+
+#[test]
+fn byte_order_mark_is_skipped() {
+    let path: PathBuf = write_script("bom.rox", b"\xef\xbb\xbfprint 1;\n");
+    let output: Output = rox(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "1\n");
+
+    let tokens: String = stdout(&rox(&["tokens", path.to_str().unwrap()]));
+    assert!(tokens.starts_with("1:1\tTokenPrint\t\"print\"\t3..8\n"), "{}", tokens);
+}
+
+#[test]
+fn shebang_line_is_skipped() {
+    let path: PathBuf = write_script("shebang.rox", b"#!/usr/bin/env rox\nprint 2;\n");
+    let output: Output = rox(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "2\n");
+
+    let tokens: String = stdout(&rox(&["tokens", path.to_str().unwrap()]));
+    assert!(tokens.starts_with("2:1\tTokenPrint\t\"print\"\t19..24\n"), "{}", tokens);
+}
+
+#[test]
+fn crlf_line_endings_are_normalised() {
+    let path: PathBuf = write_script("crlf.rox", b"print \"a\r\nb\";\r\nprint 3;\r\n");
+    let output: Output = rox(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "a\nb\n3\n");
+
+    let tokens: String = stdout(&rox(&["tokens", path.to_str().unwrap()]));
+    assert!(tokens.contains("2:3\tTokenSemicolon\t\";\"\t12..13\n"), "{}", tokens);
+    assert!(tokens.contains("3:1\tTokenPrint\t\"print\"\t15..20\n"), "{}", tokens);
+
+    let path: PathBuf = write_script("crlf_error.rox", b"print 1;\r\nprint @;\r\n");
+    let output: Output = rox(&[path.to_str().unwrap()]);
+    let stderr: String = String::from_utf8_lossy(&output.stderr).to_string();
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr.contains("crlf_error.rox:2:7\n"), "{}", stderr);
+    assert!(stderr.contains("2 | print @;\n"), "{}", stderr);
+}
+
+#[test]
+fn unreadable_files_exit_with_66() {
+    let missing: PathBuf = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing.rox");
+    let output: Output = rox(&[missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(66));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Could not read file"));
+
+    let path: PathBuf = write_script("invalid_utf8.rox", b"print \xff;\n");
+    for args in [vec![path.to_str().unwrap()], vec!["tokens", path.to_str().unwrap()]] {
+        let output: Output = rox(&args);
+        let stderr: String = String::from_utf8_lossy(&output.stderr).to_string();
+        assert_eq!(output.status.code(), Some(66));
+        assert!(stderr.contains("invalid UTF-8 at byte 6."), "{}", stderr);
+        assert!(output.stdout.is_empty());
+    }
+}
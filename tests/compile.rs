@@ -0,0 +1,73 @@
+//! Checks the diagnostics `compiler::compile` hands back to library users.
+
+#![allow(clippy::needless_return)]
+
+use rox::{compiler, diagnostic, vm};
+
+fn compile_errors(source: &str) -> Vec<diagnostic::Diagnostic> {
+    let mut virtual_machine: vm::VM = vm::VM::new();
+    match compiler::compile(source.to_string(), &mut virtual_machine, compiler::CompileOptions::new()) {
+        Ok(_) => panic!("expected {:?} to fail to compile", source),
+        Err(diagnostics) => return diagnostics,
+    }
+}
+
+#[test]
+fn successful_compile_runs() {
+    let mut virtual_machine: vm::VM = vm::VM::new();
+    let options: compiler::CompileOptions = compiler::CompileOptions::new();
+    let compiled: compiler::CompiledFunction =
+        match compiler::compile("var a = 1 + 2;".to_string(), &mut virtual_machine, options) {
+            Ok(compiled) => compiled,
+            Err(diagnostics) => panic!("unexpected diagnostics: {:?}", diagnostics),
+        };
+    assert!(matches!(
+        virtual_machine.execute(compiled),
+        vm::InterpretResult::InterpretOk
+    ));
+}
+
+#[test]
+fn every_independent_error_is_reported() {
+    let diagnostics: Vec<diagnostic::Diagnostic> = compile_errors("print 1 +;\nvar = 2;\nreturn 3;\n");
+    let codes: Vec<diagnostic::Code> = diagnostics.iter().map(|d| d.get_code()).collect();
+    assert_eq!(
+        codes,
+        vec![
+            diagnostic::Code::ExpectedToken,
+            diagnostic::Code::ExpectedToken,
+            diagnostic::Code::InvalidReturn,
+        ]
+    );
+    assert!(diagnostics
+        .iter()
+        .all(|d| d.get_severity() == diagnostic::Severity::Error));
+}
+
+#[test]
+fn primary_span_points_at_the_offending_token() {
+    let diagnostics: Vec<diagnostic::Diagnostic> = compile_errors("var a = 1;\nprint a @ 2;\n");
+    assert_eq!(diagnostics.len(), 1);
+
+    let span: &diagnostic::Span = diagnostics[0].get_span();
+    assert_eq!(diagnostics[0].get_code(), diagnostic::Code::InvalidToken);
+    assert_eq!(diagnostics[0].get_message(), "Unexpected character.");
+    assert_eq!((span.get_line(), span.get_column()), (2, 9));
+    assert_eq!(span.get_range(), 19..20);
+}
+
+#[test]
+fn secondary_labels_point_at_related_code() {
+    let diagnostics: Vec<diagnostic::Diagnostic> = compile_errors("{\n  var a = 1;\n  var a = 2;\n}\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].get_code(), diagnostic::Code::InvalidVariable);
+    assert_eq!(diagnostics[0].get_span().get_line(), 3);
+
+    let labels: &[diagnostic::Label] = diagnostics[0].get_labels();
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0].get_message(), "first declared here");
+    assert_eq!(
+        (labels[0].get_span().get_line(), labels[0].get_span().get_column()),
+        (2, 7)
+    );
+}
@@ -0,0 +1,177 @@
+//! Runs every `.rox` script under `tests/lox` through the `rox` binary, once
+//! with the optimiser off and once with it on, so both runs have to agree.
+//!
+//! Expectations are written as comments in the scripts themselves:
+//!
+//! - `// expect: <text>` is a line the script must print, in order.
+//! - `// expect error: <text>` means compilation fails (exit 65) with an
+//!   `error[<code>]: <text>` diagnostic.
+//! - `// expect runtime error: <text>` means the script fails at runtime
+//!   (exit 70) and the output mentions `<text>`.
+//!
+//! Scripts under `tests/diagnostics` are compared against the `.stderr` file
+//! next to them instead, to check how diagnostics are rendered.
+
+#![allow(clippy::needless_return)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+struct Expectation {
+    output: Vec<String>,
+    errors: Vec<String>,
+    exit_code: i32,
+}
+
+fn parse_expectation(source: &str) -> Expectation {
+    let mut expectation = Expectation {
+        output: Vec::new(),
+        errors: Vec::new(),
+        exit_code: 0,
+    };
+
+    for line in source.lines() {
+        if let Some((_, text)) = line.split_once("// expect runtime error: ") {
+            expectation.errors.push(text.to_string());
+            expectation.exit_code = 70;
+        } else if let Some((_, text)) = line.split_once("// expect error: ") {
+            expectation.errors.push(text.to_string());
+            expectation.exit_code = 65;
+        } else if let Some((_, text)) = line.split_once("// expect: ") {
+            expectation.output.push(text.to_string());
+        }
+    }
+
+    return expectation;
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).expect("Error reading test directory") {
+        let path: PathBuf = entry.expect("Error reading test directory").path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts);
+        } else if path.extension().is_some_and(|extension| extension == "rox") {
+            scripts.push(path);
+        }
+    }
+}
+
+fn run_script(path: &Path, opt_level: &str) -> Result<(), String> {
+    let source: String = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expectation: Expectation = parse_expectation(&source);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rox"))
+        .arg("--opt-level")
+        .arg(opt_level)
+        .arg(path)
+        .output()
+        .map_err(|e| e.to_string())?;
+    let stdout: String = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr: String = String::from_utf8_lossy(&output.stderr).to_string();
+
+    let exit_code: i32 = output.status.code().unwrap_or(-1);
+    if exit_code != expectation.exit_code {
+        return Err(format!(
+            "expected exit code {}, got {}\nstdout:\n{}stderr:\n{}",
+            expectation.exit_code, exit_code, stdout, stderr
+        ));
+    }
+
+    for error in &expectation.errors {
+        // The source snippet under a compile error repeats the expectation
+        // comment, so only the diagnostic's own headline counts.
+        let found: bool = if expectation.exit_code == 65 {
+            stderr.lines().any(|line| {
+                line.starts_with("error[")
+                    && line.split_once("]: ").is_some_and(|(_, message)| message == error)
+            })
+        } else {
+            stdout.contains(error.as_str()) || stderr.contains(error.as_str())
+        };
+        if !found {
+            return Err(format!(
+                "expected error {:?}\nstdout:\n{}stderr:\n{}",
+                error, stdout, stderr
+            ));
+        }
+    }
+
+    if expectation.exit_code != 65 {
+        let printed: Vec<&str> = stdout.lines().collect();
+        if printed != expectation.output {
+            return Err(format!(
+                "expected output {:?}, got {:?}\nstderr:\n{}",
+                expectation.output, printed, stderr
+            ));
+        }
+    }
+
+    return Ok(());
+}
+
+#[test]
+fn lox_scripts() {
+    let mut scripts: Vec<PathBuf> = Vec::new();
+    collect_scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"),
+        &mut scripts,
+    );
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts found under tests/lox");
+
+    let mut failures: Vec<String> = Vec::new();
+    for script in &scripts {
+        for opt_level in ["0", "1"] {
+            if let Err(message) = run_script(script, opt_level) {
+                failures.push(format!(
+                    "{} (--opt-level {}): {}",
+                    script.display(),
+                    opt_level,
+                    message
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} scripts failed:\n\n{}",
+        failures.len(),
+        scripts.len(),
+        failures.join("\n\n")
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let root: &Path = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut scripts: Vec<PathBuf> = Vec::new();
+    collect_scripts(&root.join("tests/diagnostics"), &mut scripts);
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts found under tests/diagnostics");
+
+    let mut failures: Vec<String> = Vec::new();
+    for script in &scripts {
+        let relative: &Path = script.strip_prefix(root).expect("script outside the crate");
+        let output = Command::new(env!("CARGO_BIN_EXE_rox"))
+            .current_dir(root)
+            .arg(relative)
+            .output()
+            .expect("Error running rox");
+        let stderr: String = String::from_utf8_lossy(&output.stderr).to_string();
+        let expected: String = std::fs::read_to_string(script.with_extension("stderr"))
+            .expect("Error reading expected stderr");
+
+        if output.status.code() != Some(65) || stderr != expected {
+            failures.push(format!(
+                "{}: exit code {:?}\nexpected:\n{}got:\n{}",
+                relative.display(),
+                output.status.code(),
+                expected,
+                stderr
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}